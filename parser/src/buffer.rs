@@ -87,6 +87,21 @@ impl<'a> Cursor<'a> {
     pub fn offset(self, other: Cursor) -> usize {
         unsafe { other.ptr.offset_from(self.ptr) as usize }
     }
+
+    /// The number of tokens left before the end of the buffer.
+    pub fn remaining(&self) -> usize {
+        unsafe { self.end.offset_from(self.ptr) as usize }
+    }
+
+    /// The total number of tokens in the buffer this cursor walks over.
+    pub fn total(&self) -> usize {
+        unsafe { self.end.offset_from(self.start) as usize }
+    }
+
+    /// The number of tokens consumed so far.
+    pub fn position(&self) -> usize {
+        self.total() - self.remaining()
+    }
 }
 
 impl TokenBuffer {
@@ -137,6 +152,17 @@ impl TokenBuffer {
     pub fn is_empty(&self) -> bool {
         self.tokens.is_empty()
     }
+
+    /// Buffers from the lexer end with an empty entry marking the end, which `to_tokens`
+    /// doesn't produce. Without it the last token would be taken as the end, so this adds
+    /// one unless it is already there.
+    pub fn terminated(self) -> TokenBuffer {
+        if let Some(Entry::Empty) = self.tokens.last() {
+            self
+        } else {
+            self.extend(TokenBuffer::new(vec![Entry::Empty]))
+        }
+    }
 }
 
 impl Spanned for Entry {
@@ -162,8 +188,8 @@ impl std::fmt::Display for TokenBuffer {
                     write!(f, "{} ", p.ch)?;
                 },
                 Entry::Literal(l) => match l {
-                    Literal::Int(i) => write!(f, "{} ", i.int)?,
-                    Literal::Float(v) => write!(f, "{} ", v.float)?,
+                    Literal::Int(i) => write!(f, "{} ", i)?,
+                    Literal::Float(v) => write!(f, "{} ", v)?,
                     Literal::Char(c) => write!(f, "{:?} ", c.ch)?,
                     Literal::String(s) => write!(f, "{:?} ", s.text)?
                 }
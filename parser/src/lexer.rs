@@ -11,29 +11,75 @@ pub struct Lexer<'a> {
     file: FileId,
     source: &'a str,
     chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    base: usize,
     start: Position,
     pos: Position,
+    errors: usize,
+    punctuation: Option<&'a str>,
+    aliases: &'a [(char, char)],
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(source: &'a str, file: FileId, reporter: &'a Reporter) -> Lexer<'a> {
+        Lexer::with_start(source, file, reporter, Position::default())
+    }
+
+    /// Creates a lexer for a fragment of a larger file, where `source` begins at `start`.
+    /// All spans produced are relative to the larger file rather than the fragment.
+    pub fn with_start(source: &'a str, file: FileId, reporter: &'a Reporter, start: Position) -> Lexer<'a> {
         Lexer {
             reporter,
             file,
             source,
             chars: source.char_indices().peekable(),
-            start: Position::default(),
-            pos: Position::default(),
+            base: start.offset,
+            start,
+            pos: start,
+            errors: 0,
+            punctuation: None,
+            aliases: &[],
         }
     }
 
+    /// Restricts punctuation to the characters in `chars`, any other character is reported
+    /// as unexpected and skipped. By default every character is accepted.
+    pub fn punctuation(mut self, chars: &'a str) -> Lexer<'a> {
+        self.punctuation = Some(chars);
+        self
+    }
+
+    /// Lexes the first character of every pair as the punctuation character it is paired
+    /// with, such as `×` as `*`. Spans still cover the original character.
+    pub fn aliases(mut self, aliases: &'a [(char, char)]) -> Lexer<'a> {
+        self.aliases = aliases;
+        self
+    }
+
+    fn alias(&self, ch: char) -> char {
+        self.aliases.iter().find(|(from, _)| *from == ch).map_or(ch, |(_, to)| *to)
+    }
+
+    fn is_punct(&self, ch: char) -> bool {
+        let ch = self.alias(ch);
+
+        self.punctuation.map_or(true, |chars| chars.contains(ch))
+    }
+
+    /// The number of errors reported so far.
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
     pub fn run(&mut self) -> TokenBuffer {
         let mut tokens = Vec::new();
         
         while !self.eof() {
             match self.next() {
                 Ok(t) => tokens.push(t),
-                Err(e) => self.reporter.add(e),
+                Err(e) => {
+                    self.reporter.add(e);
+                    self.errors += 1;
+                }
             }
         }
 
@@ -63,6 +109,11 @@ impl<'a> Lexer<'a> {
             'a'..='z' | 'A'..='Z' | '_' => self.ident(),
             c if c.is_xid_start() => self.ident(),
             '\0' => Ok(Entry::Empty),
+            ch if !self.is_punct(ch) => Err(Diagnostic::new(
+                Severity::Error,
+                None,
+                format!("Unexpected character {:?}", ch),
+            ).label(Severity::Error, self.span(), None::<String>)),
             ch => {
                 let spacing = if match self.peek() {
                     'r' if self.peek_n(1) == '"' => false,
@@ -71,7 +122,7 @@ impl<'a> Lexer<'a> {
                     '0'..='9' => false,
                     'a'..='z' | 'A'..='Z' | '_' => false,
                     ' ' | '\t' | '\r' | '\n' => false,
-                    _ => true,
+                    next => self.is_punct(next),
                 } {
                     Spacing::Joint
                 } else {
@@ -80,7 +131,7 @@ impl<'a> Lexer<'a> {
                 
                 Ok(Entry::Punct(Punct {
                     span: self.span(),
-                    ch,
+                    ch: self.alias(ch),
                     spacing,
                 }))
             }
@@ -103,9 +154,21 @@ impl<'a> Lexer<'a> {
                             self.advance();
                         }
                     } else if self.peek_n(1) == '*' {
+                        let start = self.pos;
+                        
                         self.advance();
                         blocks += 1;
                         
+                        let open = Span {
+                            start,
+                            end: Position {
+                                col: self.pos.col + 1,
+                                offset: self.pos.offset + 1,
+                                ..self.pos
+                            },
+                            file: self.file,
+                        };
+                        
                         while !self.eof() && blocks > 0 {
                             self.advance();
                             
@@ -120,6 +183,15 @@ impl<'a> Lexer<'a> {
                                 self.advance();
                             }
                         }
+                        
+                        if blocks > 0 {
+                            self.errors += 1;
+                            self.reporter.add(Diagnostic::new(
+                                Severity::Error,
+                                None,
+                                "Unterminated block comment",
+                            ).label(Severity::Error, open, None::<String>));
+                        }
                     } else {
                         break;
                     }
@@ -138,6 +210,19 @@ impl<'a> Lexer<'a> {
             }
         }
         
+        let text = self.text();
+        
+        // `_5` would otherwise silently become an identifier.
+        if text.starts_with('_') && text.chars().any(|c| c.is_ascii_digit())
+            && text.chars().all(|c| c == '_' || c.is_ascii_digit())
+        {
+            return Err(Diagnostic::new(
+                Severity::Error,
+                None,
+                "Numeric literals cannot start with an underscore",
+            ).label(Severity::Error, self.span(), None::<String>));
+        }
+        
         Ok(Entry::Ident(Ident {
             span: self.span(),
             name: self.text().to_string(),
@@ -199,61 +284,48 @@ impl<'a> Lexer<'a> {
     }
     
     fn number(&mut self) -> Result<Entry> {
-        let mut count = 0;
-        
-        while !self.eof() {
-            match self.peek() {
-                '_' if self.peek_n(1) >= '0' && self.peek_n(1) <= '9' => self.advance(),
-                '0'..='9' => self.advance(),
-                _ => break,
-            }
-            
-            count += 1;
-        }
-        
         let mut float = false;
+        let radix = match self.peek() {
+            'x' if self.text() == "0" => 16,
+            'o' if self.text() == "0" => 8,
+            'b' if self.text() == "0" => 2,
+            _ => 10,
+        };
         
-        if self.peek() == '.' && self.peek_n(1) != '.' {
+        if radix != 10 {
             self.advance();
-            float = true;
             
-            while !self.eof() {
-                match self.peek() {
-                    '_' if self.peek_n(1) >= '0' && self.peek_n(1) <= '9' => self.advance(),
-                    '0'..='9' => self.advance(),
-                    _ => break,
-                }
+            if self.digits(radix)? == 0 {
+                return Err(Diagnostic::new(
+                    Severity::Error,
+                    None,
+                    format!("Expected digits after `{}`", self.text()),
+                ).label(Severity::Error, self.span(), None::<String>));
             }
+        } else {
+            self.digits(10)?;
             
-            if self.peek() == 'e' && (self.peek_n(1) == '+' || self.peek_n(1) == '-') {
+            if self.peek() == '.' && self.peek_n(1) != '.' {
                 self.advance();
-                self.advance();
-                
-                while !self.eof() {
-                    match self.peek() {
-                        '0'..='9' => self.advance(),
-                        _ => break,
-                    }
-                }
+                float = true;
+                self.digits(10)?;
             }
-        } else if self.peek() == 'x' && count == 0 {
-            self.advance();
             
-            while !self.eof() {
-                match self.peek() {
-                    '_' if self.peek_n(1) >= '0' && self.peek_n(1) <= '9' => self.advance(),
-                    '0'..='9' | 'a'..='z' | 'A'..='Z' => self.advance(),
-                    _ => break,
+            // No suffix starts with an `e`, so it always begins an exponent.
+            if self.peek() == 'e' || self.peek() == 'E' {
+                self.advance();
+                float = true;
+                
+                if self.peek() == '+' || self.peek() == '-' {
+                    self.advance();
                 }
-            }
-        } else if self.peek() == 'b' && count == 0 {
-            self.advance();
-
-            while !self.eof() {
-                match self.peek() {
-                    '_' if self.peek_n(1) >= '0' && self.peek_n(1) <= '9' => self.advance(),
-                    '0' | '1' => self.advance(),
-                    _ => break,
+                
+                if self.digits(10)? == 0 {
+                    return Err(Diagnostic::new(
+                        Severity::Error,
+                        None,
+                        "Expected digits in the exponent",
+                    ).label(Severity::Error, self.span(), None::<String>));
                 }
             }
         }
@@ -271,7 +343,8 @@ impl<'a> Lexer<'a> {
         }
 
         if float || ty == "f32" || ty == "f64" || ty == "float" {
-            let val: f64 = self.source[self.start.offset..self.pos.offset - ty.len()]
+            let text = self.text();
+            let val: f64 = text[..text.len() - ty.len()]
                 .replace('_', "")
                 .parse()
                 .map_err(|_| {
@@ -281,80 +354,133 @@ impl<'a> Lexer<'a> {
                         "Invalid floating point literal",
                     ).label(Severity::Error, self.span(), None::<String>)
                 })?;
+            let ty = match ty.as_str() {
+                "f32" => FloatType::F32,
+                "f64" => FloatType::F64,
+                "" => FloatType::Unknown,
+                _ => {
+                    let span = self.span();
+                    let span = Span {
+                        start: Position {
+                            line: span.end.line,
+                            col: span.end.col - ty.len(),
+                            offset: span.end.offset - ty.len(),
+                        },
+                        .. span
+                    };
+                    
+                    return Err(Diagnostic::new(
+                        Severity::Error,
+                        None,
+                        "Invalid number type",
+                    ).label(Severity::Error, span, None::<String>))
+                }
+            };
+            
+            if ty == FloatType::F32 && val.is_finite() && (val as f32).is_infinite() {
+                return Err(Diagnostic::new(
+                    Severity::Error,
+                    None,
+                    "Float literal is out of range for `f32`",
+                ).label(Severity::Error, self.span(), None::<String>));
+            }
+            
             Ok(Entry::Literal(Literal::Float(FloatLiteral {
                 span: self.span(),
                 float: val.to_bits(),
-                ty: match ty.as_str() {
-                    "f32" => FloatType::F32,
-                    "f64" => FloatType::F64,
-                    "" => FloatType::Unknown,
-                    _ => {
-                        let span = self.span();
-                        let span = Span {
-                            start: Position {
-                                line: span.end.line,
-                                col: span.end.col - ty.len(),
-                                offset: span.end.offset - ty.len(),
-                            },
-                            .. span
-                        };
-                        
-                        return Err(Diagnostic::new(
-                            Severity::Error,
-                            None,
-                            "Invalid number type",
-                        ).label(Severity::Error, span, None::<String>))
-                    }
-                },
+                ty,
             })))
         } else {
-            let text = self.source[self.start.offset..self.pos.offset - ty.len()].replace('_', "");
-            let val = if text.contains('x') && text.find('x') == Some(1) {
-                u128::from_str_radix(&text[2..], 16)
-            } else if text.contains('b') && text.find('b') == Some(1) {
-                u128::from_str_radix(&text[2..], 2)
-            } else {
-                u128::from_str_radix(&text, 10)
-            }.map_err(|_| {
+            let text = self.text();
+            let text = text[..text.len() - ty.len()].replace('_', "");
+            let digits = if radix == 10 { &text[..] } else { &text[2..] };
+            let val = u128::from_str_radix(digits, radix).map_err(|_| {
                 Diagnostic::new(Severity::Error, None, "Invalid integer literal")
                     .label(Severity::Error, self.span(), None::<String>)
             })?;
             
+            let ty = match ty.as_str() {
+                "u8" => IntType::U8,
+                "u16" => IntType::U16,
+                "u32" => IntType::U32,
+                "u64" => IntType::U64,
+                "u128" => IntType::U128,
+                "i8" => IntType::I8,
+                "i16" => IntType::I16,
+                "i32" => IntType::I32,
+                "i64" => IntType::I64,
+                "i128" => IntType::I128,
+                "" => IntType::Unknown,
+                _ => {
+                    let span = self.span();
+                    let span = Span {
+                        start: Position {
+                            line: span.end.line,
+                            col: span.end.col - ty.len(),
+                            offset: span.end.offset - ty.len(),
+                        },
+                        .. span
+                    };
+                    
+                    return Err(Diagnostic::new(
+                        Severity::Error,
+                        None,
+                        "Invalid number type",
+                    ).label(Severity::Error, span, None::<String>));
+                }
+            };
+            
+            if ty.max().map_or(false, |max| val > max) {
+                return Err(Diagnostic::new(
+                    Severity::Error,
+                    None,
+                    format!("Integer literal is out of range for `{}`", ty),
+                ).label(Severity::Error, self.span(), None::<String>));
+            }
+            
             Ok(Entry::Literal(Literal::Int(IntLiteral {
                 span: self.span(),
                 int: val,
-                ty: match ty.as_str() {
-                    "u8" => IntType::U8,
-                    "u16" => IntType::U16,
-                    "u32" => IntType::U32,
-                    "u64" => IntType::U64,
-                    "u128" => IntType::U128,
-                    "i8" => IntType::I8,
-                    "i16" => IntType::I16,
-                    "i32" => IntType::I32,
-                    "i64" => IntType::I64,
-                    "i128" => IntType::I128,
-                    "" => IntType::Unknown,
-                    _ => {
-                        let span = self.span();
-                        let span = Span {
-                            start: Position {
-                                line: span.end.line,
-                                col: span.end.col - ty.len(),
-                                offset: span.end.offset - ty.len(),
-                            },
-                            .. span
-                        };
-                        
+                ty,
+            })))
+        }
+    }
+    
+    /// Consumes the digits of a number in `radix` along with any underscores separating
+    /// them, returning the number of digits. Underscores must be followed by a digit.
+    fn digits(&mut self, radix: u32) -> Result<usize> {
+        let mut count = 0;
+        
+        while !self.eof() {
+            match self.peek() {
+                '_' => {
+                    let start = self.pos;
+                    
+                    while self.peek() == '_' {
+                        self.advance();
+                    }
+                    
+                    if !self.peek().is_digit(radix) {
                         return Err(Diagnostic::new(
                             Severity::Error,
                             None,
-                            "Invalid number type",
-                        ).label(Severity::Error, span, None::<String>));
+                            "Numeric literals cannot end with an underscore",
+                        ).label(Severity::Error, Span {
+                            start,
+                            end: self.pos,
+                            file: self.file,
+                        }, None::<String>));
                     }
-                },
-            })))
+                }
+                c if c.is_digit(radix) => {
+                    self.advance();
+                    count += 1;
+                }
+                _ => break,
+            }
         }
+        
+        Ok(count)
     }
     
     fn escape(&mut self) -> Result<char> {
@@ -365,6 +491,7 @@ impl<'a> Lexer<'a> {
         match ch {
             '"' => Ok('"'),
             '\'' => Ok('\''),
+            '\\' => Ok('\\'),
             'n' => Ok('\n'),
             'r' => Ok('\r'),
             't' => Ok('\t'),
@@ -410,7 +537,7 @@ impl<'a> Lexer<'a> {
     }
 
     fn text(&self) -> &str {
-        &self.source[self.start.offset..self.pos.offset]
+        &self.source[self.start.offset - self.base..self.pos.offset - self.base]
     }
 
     fn eof(&mut self) -> bool {
@@ -441,7 +568,126 @@ impl<'a> Lexer<'a> {
                 self.pos.col += 1;
             }
 
-            self.pos.offset = idx + ch.len_utf8();
+            self.pos.offset = self.base + idx + ch.len_utf8();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Lexes `source`, returning its tokens without the trailing `Entry::Empty` and the
+    /// number of errors. Nothing is printed, so the default file will do.
+    fn lex(source: &str) -> (Vec<Entry>, usize) {
+        let reporter = Reporter::default();
+        let mut lexer = Lexer::new(source, Span::default().file, &reporter);
+        let mut tokens = lexer.run().tokens;
+        
+        tokens.pop();
+        (tokens, lexer.errors())
+    }
+    
+    /// Lexes `source`, which must be a single integer literal.
+    fn lex_int(source: &str) -> IntLiteral {
+        match lex(source) {
+            (tokens, 0) => match &tokens[..] {
+                [Entry::Literal(Literal::Int(lit))] => lit.clone(),
+                _ => panic!("`{}` isn't a single integer literal", source),
+            },
+            (_, errors) => panic!("`{}` has {} errors", source, errors),
+        }
+    }
+    
+    fn offsets(span: Span) -> (usize, usize) {
+        (span.start.offset, span.end.offset)
+    }
+    
+    #[test]
+    fn radix_prefixes() {
+        assert_eq!(lex_int("0x1F").int, 31);
+        assert_eq!(lex_int("0xff").int, 255);
+        assert_eq!(lex_int("0xAbC").int, 0xabc);
+        assert_eq!(lex_int("0o17").int, 15);
+        assert_eq!(lex_int("0b1010").int, 10);
+        assert_eq!(offsets(lex_int("0x1F").span), (0, 4));
+        
+        for source in &["0x", "0o", "0b", "0b2", "0o8"] {
+            assert_eq!(lex(source).1, 1, "{}", source);
+        }
+    }
+    
+    #[test]
+    fn underscores_separate_digits() {
+        assert_eq!(lex_int("1_000_000").int, 1_000_000);
+        assert_eq!(lex_int("1__0").int, 10);
+        assert_eq!(lex_int("0xff_ff").int, 0xffff);
+        assert_eq!(lex_int("0b1_0").int, 2);
+        assert_eq!(offsets(lex_int("1_000").span), (0, 5));
+        
+        for source in &["5_", "1_.5", "0x_", "_5", "_1_000"] {
+            assert_eq!(lex(source).1, 1, "{}", source);
+        }
+        
+        assert!(matches!(&lex("_x5").0[..], [Entry::Ident(_)]));
+    }
+    
+    #[test]
+    fn comments_are_skipped() {
+        match lex("1 /* a /* nested */ b */ + // line\n2") {
+            (tokens, 0) => match &tokens[..] {
+                [Entry::Literal(_), Entry::Punct(plus), Entry::Literal(Literal::Int(two))] => {
+                    assert_eq!(offsets(plus.span), (25, 26));
+                    assert_eq!((two.span.start.line, two.span.start.col), (1, 0));
+                }
+                tokens => panic!("lexed to {:?}", tokens),
+            },
+            (_, errors) => panic!("{} errors", errors),
+        }
+    }
+    
+    #[test]
+    fn unterminated_block_comment() {
+        assert_eq!(lex("1 /* a").1, 1);
+        assert_eq!(lex("1 /* a /* b */").1, 1);
+    }
+    
+    #[test]
+    fn float_exponents() {
+        let floats = [("1.5e3", 1500.0), ("2E-4", 0.0002), ("1e+5", 1e5), ("3e0", 3.0)];
+        
+        for &(source, val) in &floats {
+            match &lex(source) {
+                (tokens, 0) => match &tokens[..] {
+                    [Entry::Literal(Literal::Float(lit))] => {
+                        assert_eq!(lit.float, f64::to_bits(val), "{}", source);
+                    }
+                    tokens => panic!("`{}` lexed to {:?}", source, tokens),
+                },
+                (_, errors) => panic!("`{}` has {} errors", source, errors),
+            }
+        }
+        
+        for source in &["1e", "1e+", "2.5E-", "1ex"] {
+            assert_eq!(lex(source).1, 1, "{}", source);
+        }
+    }
+    
+    #[test]
+    fn type_suffixes() {
+        assert_eq!(lex_int("5").ty, IntType::Unknown);
+        assert_eq!(lex_int("10u8").ty, IntType::U8);
+        assert_eq!(lex_int("255u8").int, 255);
+        assert_eq!(lex_int("0xffi16").ty, IntType::I16);
+        assert_eq!(offsets(lex_int("10u8").span), (0, 4));
+        
+        match &lex("3.5f32").0[..] {
+            [Entry::Literal(Literal::Float(lit))] => assert_eq!(lit.ty, FloatType::F32),
+            tokens => panic!("lexed to {:?}", tokens),
+        }
+        
+        for source in &["300u8", "128i8", "1e39f32", "10q8", "2.5u8"] {
+            assert_eq!(lex(source).1, 1, "{}", source);
         }
     }
 }
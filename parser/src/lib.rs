@@ -8,6 +8,8 @@ pub mod ident;
 pub mod punct;
 pub mod literal;
 pub mod lexer;
+pub mod punctuated;
+pub mod snapshot;
 
 pub fn parse<T: parse::Parse<D>, D>(
     source: &str,
@@ -61,6 +61,25 @@ pub enum FloatType {
     Unknown,
 }
 
+impl IntType {
+    /// The largest value of the type, or `None` for `Unknown`.
+    pub fn max(&self) -> Option<u128> {
+        match self {
+            IntType::U8 => Some(u8::MAX as u128),
+            IntType::U16 => Some(u16::MAX as u128),
+            IntType::U32 => Some(u32::MAX as u128),
+            IntType::U64 => Some(u64::MAX as u128),
+            IntType::U128 => Some(u128::MAX),
+            IntType::I8 => Some(i8::MAX as u128),
+            IntType::I16 => Some(i16::MAX as u128),
+            IntType::I32 => Some(i32::MAX as u128),
+            IntType::I64 => Some(i64::MAX as u128),
+            IntType::I128 => Some(i128::MAX as u128),
+            IntType::Unknown => None,
+        }
+    }
+}
+
 impl Literal {
     pub fn string(&self) -> Option<&str> {
         match self {
@@ -129,6 +148,18 @@ impl<D> Parse<D> for FloatLiteral {
     }
 }
 
+impl<D> Parse<D> for CharLiteral {
+    fn parse(input: ParseStream<D>) -> Result<CharLiteral> {
+        input.step(|cursor| {
+            if let Some((Literal::Char(literal), rest)) = cursor.literal() {
+                Ok((literal.clone(), rest))
+            } else {
+                Err(cursor.error("expected a character"))
+            }
+        })
+    }
+}
+
 impl<D> Parse<D> for StringLiteral {
     fn parse(input: ParseStream<D>) -> Result<StringLiteral> {
         input.step(|cursor| {
@@ -224,7 +255,27 @@ impl Display for IntLiteral {
 
 impl Display for FloatLiteral {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        write!(f, "{}{}", f64::from_bits(self.float), self.ty)
+        let val = f64::from_bits(self.float);
+        
+        // There is no literal for NaN, and the lexer never produces one. Infinity is
+        // written as an exponent too large for any float, which lexes back to it.
+        if val.is_nan() {
+            return write!(f, "NaN");
+        } else if val.is_infinite() {
+            let sign = if val < 0.0 { "-" } else { "" };
+            
+            return write!(f, "{}1e999{}", sign, self.ty);
+        }
+        
+        // `Display for f64` prints the shortest text that parses back to the same bits,
+        // but drops the fraction of whole numbers, which would then lex as an integer.
+        let text = val.to_string();
+        
+        if !text.contains('.') {
+            write!(f, "{}.0{}", text, self.ty)
+        } else {
+            write!(f, "{}{}", text, self.ty)
+        }
     }
 }
 
@@ -267,3 +318,105 @@ impl Display for FloatType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parse::reparse;
+    use diagnostics::Reporter;
+    
+    /// Lexes `source`, which must be a single float literal. Nothing is printed, so the
+    /// default file will do.
+    fn lex_float(source: &str) -> FloatLiteral {
+        let reporter = Reporter::default();
+        let buffer = Lexer::new(source, Span::default().file, &reporter).run();
+        
+        match &buffer.tokens[..] {
+            [Entry::Literal(Literal::Float(lit)), Entry::Empty] => lit.clone(),
+            _ => panic!("`{}` isn't a single float literal", source),
+        }
+    }
+    
+    fn literal(val: f64) -> FloatLiteral {
+        FloatLiteral {
+            span: Span::default(),
+            float: val.to_bits(),
+            ty: FloatType::Unknown,
+        }
+    }
+    
+    #[test]
+    fn float_display_round_trips() {
+        let mut values = vec![
+            0.0,
+            0.1,
+            1.0,
+            1.5,
+            1e300,
+            1e-300,
+            5e-324,
+            9007199254740993.0,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            f64::INFINITY,
+        ];
+        // A xorshift generator, for bit patterns from the whole range of exponents.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        
+        for _ in 0..10_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            
+            // The lexer never produces a negative literal.
+            let val = f64::from_bits(state & !(1 << 63));
+            
+            if !val.is_nan() {
+                values.push(val);
+            }
+        }
+        
+        for val in values {
+            let text = literal(val).to_string();
+            
+            assert_eq!(lex_float(&text).float, val.to_bits(), "{}", text);
+        }
+    }
+    
+    #[test]
+    fn float_exponents_round_trip() {
+        for source in &["1e300", "1e-300", "2.5E10", "1e+5", "1e999", "1e300f64"] {
+            let lit = lex_float(source);
+            let again = lex_float(&lit.to_string());
+            
+            assert_eq!((again.float, again.ty), (lit.float, lit.ty), "{}", source);
+        }
+    }
+    
+    #[test]
+    fn literals_reparse() {
+        let sources = [
+            "42", "0x1F", "10u8", "1.5", "2.5e10f32", "'x'", "'\\n'", "\"a\\tb\"", "r\"a\\b\"",
+        ];
+        
+        for source in &sources {
+            let reporter = Reporter::default();
+            let buffer = Lexer::new(source, Span::default().file, &reporter).run();
+            let lit = match &buffer.tokens[..] {
+                [Entry::Literal(lit), Entry::Empty] => lit.clone(),
+                _ => panic!("`{}` isn't a single literal", source),
+            };
+            
+            match reparse(&lit) {
+                Ok(again) => assert_eq!(again, lit, "{}", source),
+                Err(_) => panic!("`{}` didn't reparse", source),
+            }
+        }
+    }
+    
+    #[test]
+    fn nan_has_no_literal() {
+        assert_eq!(literal(f64::NAN).to_string(), "NaN");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::buffer::{Cursor, TokenBuffer};
+use crate::buffer::{Cursor, Entry, TokenBuffer};
 use crate::error::Result;
 use crate::literal::Literal;
 use crate::token::Token;
@@ -32,6 +32,10 @@ pub struct StepCursor<'c, 'a> {
 }
 
 impl<'a, D> ParseBuffer<'a, D> {
+    /// The cursor is stored with its lifetime erased to `'static` so that it can live in a
+    /// `Cell` next to the other state, `marker` keeps the tokens it points into borrowed for
+    /// `'a` instead. Every cursor handed out again is bound to `'a` through `cursor`, so none
+    /// can outlive the buffer. `cursor` has to end at an `Entry::Empty`, which marks the end.
     pub fn new(cursor: Cursor<'a>, reporter: &'a Reporter, data: D, start: Span) -> ParseBuffer<'a, D> {
         ParseBuffer {
             data,
@@ -43,6 +47,21 @@ impl<'a, D> ParseBuffer<'a, D> {
         }
     }
     
+    /// Creates a stream over `tokens` without lexing anything. Tokens built by `ToTokens`
+    /// have to go through [`TokenBuffer::terminated`] first.
+    pub fn from_tokens(tokens: &'a TokenBuffer, reporter: &'a Reporter, data: D) -> ParseBuffer<'a, D> {
+        use diagnostics::Spanned;
+        
+        debug_assert!(matches!(tokens.tokens.last(), Some(Entry::Empty) | None), "tokens aren't terminated");
+        
+        let start = match tokens.tokens.first() {
+            Some(Entry::Empty) | None => Span::default(),
+            Some(token) => token.span(),
+        };
+        
+        ParseBuffer::new(tokens.begin(), reporter, data, start)
+    }
+    
     pub fn span(&self) -> Span {
         if self.is_empty() {
             self.prev_span.borrow().clone()
@@ -54,6 +73,11 @@ impl<'a, D> ParseBuffer<'a, D> {
     pub fn prev_span(&self) -> Span {
         self.prev_span.borrow().clone()
     }
+
+    /// The span from `start` up to the last token parsed, see [`join`].
+    pub fn span_from(&self, start: Span) -> Span {
+        join(start, self.prev_span())
+    }
     
     pub fn error<T, I: Into<String>>(&self, msg: I, code: impl Into<Option<u16>>) -> Result<T> {
         Err(Diagnostic::new(Severity::Error, code, msg)
@@ -82,28 +106,34 @@ impl<'a, D> ParseBuffer<'a, D> {
         T::peek(self.cursor())
     }
     
-    pub fn peek2<T: Token>(&self) -> bool
+    /// Peeks at the token `n` tokens ahead, `peek_n::<T>(0)` is the same as `peek::<T>()`.
+    pub fn peek_n<T: Token>(&self, n: usize) -> bool
     where
         D: Copy
     {
         let ahead = self.fork();
-        skip(&ahead) && ahead.peek::<T>()
+        (0..n).all(|_| skip(&ahead)) && ahead.peek::<T>()
+    }
+    
+    pub fn peek2<T: Token>(&self) -> bool
+    where
+        D: Copy
+    {
+        self.peek_n::<T>(1)
     }
     
     pub fn peek3<T: Token>(&self) -> bool
     where
         D: Copy
     {
-        let ahead = self.fork();
-        skip(&ahead) && skip(&ahead) && ahead.peek::<T>()
+        self.peek_n::<T>(2)
     }
     
     pub fn peek4<T: Token>(&self) -> bool
     where
         D: Copy
     {
-        let ahead = self.fork();
-        skip(&ahead) && skip(&ahead) && skip(&ahead) && ahead.peek::<T>()
+        self.peek_n::<T>(3)
     }
     
     pub fn is_empty(&self) -> bool {
@@ -145,6 +175,36 @@ impl<'a, D> ParseBuffer<'a, D> {
     pub fn steps(&self, other: &ParseBuffer<D>) -> usize {
         self.cursor().offset(other.cursor())
     }
+
+    pub fn steps_from(&self, cursor: Cursor) -> usize {
+        cursor.offset(self.cursor())
+    }
+
+    pub fn remaining_tokens(&self) -> usize {
+        self.cursor().remaining()
+    }
+
+    pub fn total_tokens(&self) -> usize {
+        self.cursor().total()
+    }
+
+    /// Returns `(consumed, total)`, useful for reporting progress over large inputs.
+    pub fn progress(&self) -> (usize, usize) {
+        let cursor = self.cursor();
+
+        (cursor.position(), cursor.total())
+    }
+}
+
+/// Joins `start` and `end` into a single span covering both. After error recovery `end` can
+/// precede `start`, in which case `start` is returned unchanged rather than an inverted
+/// span. Spans in different files are never joined either.
+pub fn join(start: Span, end: Span) -> Span {
+    if start.file != end.file || end.end.offset <= start.end.offset {
+        start
+    } else {
+        start.to(end)
+    }
 }
 
 fn skip<D>(input: ParseStream<D>) -> bool {
@@ -194,7 +254,15 @@ impl<T: Parse<D>, D: Copy> Parse<D> for Vec<T> {
     fn parse(input: ParseStream<D>) -> Result<Vec<T>> {
         let mut items = Vec::new();
         while !input.is_empty() && input.fork().parse::<T>().is_ok() {
+            let before = input.cursor();
+
             items.push(input.parse::<T>()?);
+
+            // An item that parses without consuming anything would otherwise loop forever.
+            if input.steps_from(before) == 0 {
+                debug_assert!(false, "parser made no progress");
+                break;
+            }
         }
         Ok(items)
     }
@@ -235,9 +303,89 @@ impl<T: ToTokens> ToTokens for Box<T> {
 
 impl<T: ToTokens> ToTokens for Vec<T> {
     fn to_tokens(&self) -> TokenBuffer {
-        self.into_iter()
+        self.iter()
             .fold(TokenBuffer::new(Vec::new()), |acc, t| {
                 acc.extend(t.to_tokens())
             })
     }
 }
+
+/// Turns `value` back into tokens and parses those again, which should give an equal value.
+/// All of the tokens have to be consumed.
+pub fn reparse<T: Parse + ToTokens>(value: &T) -> Result<T> {
+    let reporter = Reporter::default();
+    let tokens = value.to_tokens().terminated();
+    let input = ParseBuffer::from_tokens(&tokens, &reporter, ());
+    let result = input.parse()?;
+    
+    if !input.is_empty() {
+        return input.error("unexpected trailing tokens", None);
+    }
+    
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::punct::Punct;
+    use diagnostics::{FileId, FileInfo, FileInterner, Position};
+    use intern::Intern;
+    
+    fn span(file: FileId, start: usize, end: usize) -> Span {
+        Span {
+            start: Position { line: 0, col: start, offset: start },
+            end: Position { line: 0, col: end, offset: end },
+            file
+        }
+    }
+    
+    fn offsets(span: Span) -> (usize, usize) {
+        (span.start.offset, span.end.offset)
+    }
+    
+    #[test]
+    fn join_covers_both_spans() {
+        let file = Span::default().file;
+        
+        assert_eq!(join(span(file, 0, 1), span(file, 4, 5)), span(file, 0, 5));
+        assert_eq!(join(span(file, 0, 3), span(file, 2, 5)), span(file, 0, 5));
+    }
+    
+    #[test]
+    fn join_keeps_start_when_end_is_before_it() {
+        let file = Span::default().file;
+        
+        assert_eq!(join(span(file, 4, 5), span(file, 0, 1)), span(file, 4, 5));
+        assert_eq!(join(span(file, 0, 5), span(file, 2, 3)), span(file, 0, 5));
+        assert_eq!(join(span(file, 0, 5), span(file, 0, 5)), span(file, 0, 5));
+    }
+    
+    #[test]
+    fn join_keeps_start_across_files() {
+        let files = FileInterner::new();
+        let first = FileInfo { source: String::new(), name: "first".into() }.intern(&files);
+        let second = FileInfo { source: String::new(), name: "second".into() }.intern(&files);
+        
+        assert_eq!(join(span(first, 0, 1), span(second, 4, 5)), span(first, 0, 1));
+    }
+    
+    #[test]
+    fn span_from_ends_at_last_token() {
+        let reporter = Reporter::default();
+        let tokens = Lexer::new("1 + 2", Span::default().file, &reporter).run();
+        let input = ParseBuffer::from_tokens(&tokens, &reporter, ());
+        let start = input.span();
+        
+        assert!(input.parse::<Literal>().is_ok());
+        assert!(input.parse::<Punct>().is_ok());
+        assert_eq!(offsets(input.span_from(start)), (0, 3));
+        
+        // Once the input is empty its span is that of the last token, which `join` has to
+        // accept.
+        assert!(input.parse::<Literal>().is_ok());
+        assert!(input.is_empty());
+        assert_eq!(offsets(input.span_from(start)), (0, 5));
+    }
+}
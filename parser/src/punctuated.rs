@@ -0,0 +1,141 @@
+use crate::buffer::TokenBuffer;
+use crate::error::Result;
+use crate::parse::{Parse, ParseStream, ToTokens};
+use crate::token::Token;
+
+/// A list of `T`s separated by `P`s, optionally followed by a trailing `P`.
+#[derive(Debug, Clone)]
+pub struct Punctuated<T, P> {
+    inner: Vec<(T, P)>,
+    last: Option<Box<T>>,
+}
+
+impl<T, P> Punctuated<T, P> {
+    pub fn new() -> Punctuated<T, P> {
+        Punctuated {
+            inner: Vec::new(),
+            last: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty() && self.last.is_none()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len() + if self.last.is_some() { 1 } else { 0 }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.inner.iter().map(|(t, _)| t).chain(self.last.as_deref())
+    }
+
+    /// Whether the list ends with a separator, this is also true for an empty list.
+    pub fn trailing_punct(&self) -> bool {
+        self.last.is_none()
+    }
+
+    /// Appends a value, panics if the list doesn't end with a separator.
+    pub fn push_value(&mut self, value: T) {
+        assert!(self.trailing_punct(), "expected a separator before the next value");
+
+        self.last = Some(Box::new(value));
+    }
+
+    /// Appends a separator, panics if the list doesn't end with a value.
+    pub fn push_punct(&mut self, punct: P) {
+        let last = self.last.take().expect("expected a value before the next separator");
+
+        self.inner.push((*last, punct));
+    }
+
+    pub fn into_values(self) -> Vec<T> {
+        self.inner.into_iter().map(|(t, _)| t).chain(self.last.map(|t| *t)).collect()
+    }
+
+    /// Parses values separated by `P` until the end of the input. The list may be empty
+    /// and may end with a separator.
+    pub fn parse_terminated<D>(input: ParseStream<D>) -> Result<Punctuated<T, P>>
+    where
+        T: Parse<D>,
+        P: Parse<D>,
+    {
+        Punctuated::parse_terminated_with(input, T::parse)
+    }
+
+    pub fn parse_terminated_with<D>(
+        input: ParseStream<D>,
+        parser: fn(ParseStream<D>) -> Result<T>,
+    ) -> Result<Punctuated<T, P>>
+    where
+        P: Parse<D>,
+    {
+        let mut list = Punctuated::new();
+
+        while !input.is_empty() {
+            list.push_value(parser(input)?);
+
+            if input.is_empty() {
+                break;
+            }
+
+            list.push_punct(input.parse()?);
+        }
+
+        Ok(list)
+    }
+
+    /// Parses values separated by `P` for as long as a separator follows a value. The list
+    /// contains at least one value and never ends with a separator.
+    pub fn parse_separated<D>(input: ParseStream<D>) -> Result<Punctuated<T, P>>
+    where
+        T: Parse<D>,
+        P: Parse<D> + Token,
+    {
+        Punctuated::parse_separated_with(input, T::parse)
+    }
+
+    pub fn parse_separated_with<D>(
+        input: ParseStream<D>,
+        parser: fn(ParseStream<D>) -> Result<T>,
+    ) -> Result<Punctuated<T, P>>
+    where
+        P: Parse<D> + Token,
+    {
+        let mut list = Punctuated::new();
+
+        list.push_value(parser(input)?);
+
+        while input.peek::<P>() {
+            list.push_punct(input.parse()?);
+            list.push_value(parser(input)?);
+        }
+
+        Ok(list)
+    }
+}
+
+impl<T, P> Default for Punctuated<T, P> {
+    fn default() -> Punctuated<T, P> {
+        Punctuated::new()
+    }
+}
+
+impl<T: Parse<D>, P: Parse<D>, D> Parse<D> for Punctuated<T, P> {
+    fn parse(input: ParseStream<D>) -> Result<Punctuated<T, P>> {
+        Punctuated::parse_terminated(input)
+    }
+}
+
+impl<T: ToTokens, P: ToTokens> ToTokens for Punctuated<T, P> {
+    fn to_tokens(&self) -> TokenBuffer {
+        let tokens = self
+            .inner
+            .iter()
+            .fold(TokenBuffer::new(Vec::new()), |acc, (t, p)| {
+                acc.extend(t.to_tokens()).extend(p.to_tokens())
+            });
+
+        tokens.extend(self.last.to_tokens())
+    }
+}
@@ -0,0 +1,44 @@
+use crate::buffer::{Entry, TokenBuffer};
+use crate::literal::Literal;
+use crate::punct::Spacing;
+
+/// Renders a token stream in a compact, stable form meant for snapshot tests,
+/// e.g. `2 + 3` becomes `INT(2) '+' INT(3)`. Joint punctuation is rendered as
+/// a single operator so `2 ** 3` becomes `INT(2) '**' INT(3)`.
+pub fn tokens(buffer: &TokenBuffer) -> String {
+    let mut out = Vec::new();
+    let mut punct = String::new();
+
+    for entry in &buffer.tokens {
+        if let Entry::Punct(p) = entry {
+            punct.push(p.ch);
+
+            if p.spacing == Spacing::Alone {
+                out.push(format!("'{}'", punct));
+                punct.clear();
+            }
+
+            continue;
+        }
+
+        if !punct.is_empty() {
+            out.push(format!("'{}'", punct));
+            punct.clear();
+        }
+
+        match entry {
+            Entry::Ident(ident) => out.push(format!("IDENT({})", ident.name)),
+            Entry::Literal(Literal::Int(lit)) => out.push(format!("INT({})", lit)),
+            Entry::Literal(Literal::Float(lit)) => out.push(format!("FLOAT({})", lit)),
+            Entry::Literal(Literal::Char(lit)) => out.push(format!("CHAR({})", lit)),
+            Entry::Literal(Literal::String(lit)) => out.push(format!("STR({})", lit)),
+            Entry::Punct(_) | Entry::Empty => {}
+        }
+    }
+
+    if !punct.is_empty() {
+        out.push(format!("'{}'", punct));
+    }
+
+    out.join(" ")
+}
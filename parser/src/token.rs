@@ -5,6 +5,25 @@ pub trait Token {
     fn display() -> &'static str;
 }
 
+/// Counts the characters of `s`, for checking the length given to `token!` at compile time.
+#[doc(hidden)]
+pub const fn char_count(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut count = 0;
+    let mut i = 0;
+    
+    while i < bytes.len() {
+        // Every character has exactly one byte that isn't a continuation byte.
+        if bytes[i] & 0xC0 != 0x80 {
+            count += 1;
+        }
+        
+        i += 1;
+    }
+    
+    count
+}
+
 #[macro_export]
 macro_rules! token {
     (punct $token:literal pub $name:ident/$n:literal) => {
@@ -22,7 +41,11 @@ macro_rules! token {
             span: diagnostics::Span
         }
         
-        $crate:token!(@i ident $token $name);
+        $crate::token!(@i ident $token $name);
+    };
+    
+    (keyword $token:literal pub $name:ident) => {
+        $crate::token!(ident $token pub $name);
     };
     
     (punct $token:literal $name:ident/$n:literal) => {
@@ -43,9 +66,18 @@ macro_rules! token {
         $crate::token!(@i ident $token $name);
     };
     
+    (keyword $token:literal $name:ident) => {
+        $crate::token!(ident $token $name);
+    };
+    
     (@i punct $token:literal $name:ident/$n:literal) => {
         $crate::token!(@i $token $name);
         
+        const _: () = assert!(
+            $crate::token::char_count($token) == $n,
+            concat!("wrong length for `", $token, "`")
+        );
+        
         impl<D> $crate::parse::Parse<D> for $name {
             fn parse(input: $crate::parse::ParseStream<D>) -> $crate::error::Result<$name> {
                 use diagnostics::Spanned;
@@ -112,6 +144,9 @@ macro_rules! token {
                 $crate::buffer::TokenBuffer::new({
                     let chars = $token.chars().collect::<Vec<_>>();
                     
+                    // The source may have spelled a character with a longer alias, like `×`
+                    // for `*`, so the last one ends where the token does instead of one byte
+                    // after the previous character.
                     (0..chars.len()).map(|i| $crate::buffer::Entry::Punct($crate::punct::Punct {
                         span: diagnostics::Span {
                             start: diagnostics::Position {
@@ -119,10 +154,14 @@ macro_rules! token {
                                 col: self.span.start.col + i,
                                 line: self.span.start.line
                             },
-                            end: diagnostics::Position {
-                                offset: self.span.start.offset + i + 1,
-                                col: self.span.start.col + i + 1,
-                                line: self.span.start.line
+                            end: if i == chars.len() - 1 {
+                                self.span.end
+                            } else {
+                                diagnostics::Position {
+                                    offset: self.span.start.offset + i + 1,
+                                    col: self.span.start.col + i + 1,
+                                    line: self.span.start.line
+                                }
                             },
                             file: self.span.file.clone()
                         },
@@ -215,3 +254,61 @@ macro_rules! token {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::{Entry, TokenBuffer};
+    use crate::lexer::Lexer;
+    use crate::parse::{Parse, ParseBuffer, ToTokens};
+    use crate::punct::Punct;
+    use diagnostics::{Reporter, Span};
+    
+    crate::token![punct "*" TMul/1];
+    crate::token![punct "**" TPow/2];
+    
+    fn puncts(buffer: &TokenBuffer) -> Vec<Punct> {
+        buffer.tokens.iter().filter_map(|entry| match entry {
+            Entry::Punct(punct) => Some(punct.clone()),
+            _ => None,
+        }).collect()
+    }
+    
+    /// Checks that parsing `source` as a `T` and turning it back into tokens gives the
+    /// tokens the lexer produced, spans and spacing included.
+    fn assert_round_trip<T: Parse + ToTokens>(source: &str) {
+        let reporter = Reporter::default();
+        let tokens = Lexer::new(source, Span::default().file, &reporter).aliases(&[('×', '*')]).run();
+        let input = ParseBuffer::from_tokens(&tokens, &reporter, ());
+        
+        match input.parse::<T>() {
+            Ok(token) => assert_eq!(puncts(&token.to_tokens()), puncts(&tokens)),
+            Err(_) => panic!("`{}` didn't parse", source),
+        }
+    }
+    
+    #[test]
+    fn single_punct_keeps_span() {
+        assert_round_trip::<TMul>("*");
+        assert_round_trip::<TMul>("  *");
+    }
+    
+    #[test]
+    fn aliased_punct_keeps_span() {
+        // `×` is two bytes long but a single column.
+        assert_round_trip::<TMul>("×");
+        assert_round_trip::<TMul>(" ×");
+    }
+    
+    #[test]
+    fn joint_punct_keeps_spans() {
+        assert_round_trip::<TPow>("**");
+        assert_round_trip::<TPow>("  **");
+    }
+    
+    #[test]
+    fn char_count_counts_characters() {
+        assert_eq!(super::char_count(""), 0);
+        assert_eq!(super::char_count("**"), 2);
+        assert_eq!(super::char_count("×÷"), 2);
+    }
+}
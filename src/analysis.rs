@@ -1,38 +1,600 @@
 use crate::ast::*;
-use diagnostics::{Diagnostic, Reporter, Severity, Span};
+use crate::constants::Constants;
+use crate::run::{IntMode, Overflow};
+use diagnostics::{Diagnostic, Reporter, Severity, Span, Spanned};
+use std::fmt;
 
-pub fn analyze(reporter: &Reporter, ast: &Ast) {
-    analyze_ast(reporter, ast);
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Options {
+    /// Warn about parentheses that don't change the meaning of an expression.
+    pub redundant_parens: bool,
+    pub int_mode: IntMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Str,
+}
+
+/// The variables in scope, innermost last, along with their types.
+type Scope<'a> = Vec<(&'a str, Type)>;
+
+#[derive(Clone, Copy)]
+enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy)]
+enum Parent {
+    Binary(Op, Side),
+    Cmp,
+    /// Negation or complement.
+    Unary,
+}
+
+/// Adds every problem found in `program` to `reporter` and returns whether it is free of
+/// errors. Printing them is up to the caller. `constants` holds the constant
+/// subexpressions of `program`, as found by [`Constants::find`].
+pub fn analyze(
+    reporter: &Reporter,
+    program: &[Ast],
+    constants: &Constants,
+    options: Options,
+) -> bool {
+    let mut errors = 0;
+
+    for ast in program {
+        let name_errors = analyze_ast(reporter, ast, &mut Vec::new(), constants, options.int_mode);
+
+        errors += name_errors;
+
+        // Type errors in expressions that refer to unknown names would only repeat them.
+        if name_errors == 0 {
+            if let Err(e) = infer(ast, &mut Vec::new()) {
+                reporter.add(e);
+                errors += 1;
+            }
+        }
 
-    reporter.report(true);
+        if options.redundant_parens {
+            lint_parens(reporter, ast, None);
+        }
+    }
+
+    errors == 0
 }
 
-fn analyze_ast(reporter: &Reporter, ast: &Ast) {
-    match ast {
-        Ast::Int { .. } => {}
+/// Checks that every name in `ast` refers to something and returns the number of errors
+/// reported. Types are checked separately by [`infer`].
+fn analyze_ast<'a>(
+    reporter: &Reporter,
+    ast: &'a Ast,
+    scope: &mut Scope<'a>,
+    constants: &Constants,
+    mode: IntMode,
+) -> usize {
+    crate::grow_stack(|| match ast {
+        Ast::Int { span, val } => {
+            if *val > mode.max() {
+                reporter.add(
+                    Diagnostic::new(
+                        Severity::Warning,
+                        None,
+                        format!(
+                            "{} doesn't fit in {} bits and is truncated to {}",
+                            val,
+                            mode.width,
+                            mode.mask(*val)
+                        ),
+                    )
+                    .label(Severity::Warning, *span, None::<String>),
+                );
+            }
+
+            0
+        }
+        Ast::Float { .. } | Ast::Str { .. } => 0,
         Ast::Op {
-            span,
             op,
+            op_span,
             left,
             right,
-        } => analyze_op(reporter, *span, op, left, right),
-        Ast::Group { expr, .. } => analyze_ast(reporter, expr),
+            ..
+        } => {
+            lint_op(reporter, *op_span, op, left, right, constants, mode);
+
+            analyze_ast(reporter, left, scope, constants, mode)
+                + analyze_ast(reporter, right, scope, constants, mode)
+                + analyze_divisor(reporter, op, left, right, scope, constants)
+        }
+        Ast::Cmp { left, right, .. } => {
+            analyze_ast(reporter, left, scope, constants, mode)
+                + analyze_ast(reporter, right, scope, constants, mode)
+        }
+        Ast::Neg { span, expr } => {
+            analyze_ast(reporter, expr, scope, constants, mode)
+                + analyze_neg(reporter, *span, expr, constants, mode)
+        }
+        Ast::Not { expr, .. } | Ast::Group { expr, .. } => {
+            analyze_ast(reporter, expr, scope, constants, mode)
+        }
+        Ast::Let {
+            name, value, body, ..
+        } => {
+            let errors = analyze_ast(reporter, value, scope, constants, mode);
+            // A type error in the value is reported by `infer` later on.
+            let ty = infer(value, scope).unwrap_or(Type::Int);
+
+            scope.push((name.as_str(), ty));
+
+            let errors = errors + analyze_ast(reporter, body, scope, constants, mode);
+
+            scope.pop();
+            errors
+        }
+        Ast::If {
+            cond, then, else_, ..
+        } => {
+            analyze_ast(reporter, cond, scope, constants, mode)
+                + analyze_ast(reporter, then, scope, constants, mode)
+                + analyze_ast(reporter, else_, scope, constants, mode)
+        }
+        Ast::Call {
+            span,
+            name,
+            name_span,
+            args,
+        } => {
+            args.iter()
+                .map(|arg| analyze_ast(reporter, arg, scope, constants, mode))
+                .sum::<usize>()
+                + analyze_call(reporter, *span, name, *name_span, args.len())
+        }
+        // The syntax error has already been reported, it only needs to be counted.
+        Ast::Error { .. } => 1,
+        Ast::Var { span, name } => {
+            if scope.iter().any(|(var, _)| *var == name.as_str()) {
+                0
+            } else {
+                reporter.add(
+                    Diagnostic::new(
+                        Severity::Error,
+                        None,
+                        format!("Unknown variable `{}`", name),
+                    )
+                    .label(Severity::Error, *span, None::<String>),
+                );
+
+                1
+            }
+        }
+    })
+}
+
+/// Checks a call to `name` with `args` arguments, which are checked separately.
+fn analyze_call(
+    reporter: &Reporter,
+    span: Span,
+    name: &str,
+    name_span: Span,
+    args: usize,
+) -> usize {
+    let arity = match builtin_arity(name) {
+        Some(arity) => arity,
+        None => {
+            reporter.add(
+                Diagnostic::new(
+                    Severity::Error,
+                    None,
+                    format!("Unknown function `{}`", name),
+                )
+                .label(Severity::Error, name_span, None::<String>),
+            );
+
+            return 1;
+        }
+    };
+
+    if args != arity {
+        reporter.add(
+            Diagnostic::new(
+                Severity::Error,
+                None,
+                format!(
+                    "`{}` takes {} argument{} but {} {} given",
+                    name,
+                    arity,
+                    if arity == 1 { "" } else { "s" },
+                    args,
+                    if args == 1 { "was" } else { "were" },
+                ),
+            )
+            .label(Severity::Error, span, None::<String>),
+        );
+
+        return 1;
+    }
+
+    0
+}
+
+/// The number of arguments taken by the built-in function `name`, if it exists.
+fn builtin_arity(name: &str) -> Option<usize> {
+    match name {
+        "sqrt" | "abs" | "int" | "float" => Some(1),
+        "min" | "max" | "gcd" | "lcm" => Some(2),
+        _ => None,
+    }
+}
+
+/// Warns about an operation that fails once the program runs.
+fn lint_op(
+    reporter: &Reporter,
+    op_span: Span,
+    op: &Op,
+    left: &Ast,
+    right: &Ast,
+    constants: &Constants,
+    mode: IntMode,
+) {
+    // Values are unsigned, so this fails once the program runs unless it wraps.
+    if *op == Op::Sub && mode.overflow == Overflow::Trap {
+        if let (Some(left), Some(right)) = (constants.get(left), constants.get(right)) {
+            if left < right {
+                reporter.add(
+                    Diagnostic::new(
+                        Severity::Warning,
+                        None,
+                        format!("Subtracting {} from {} underflows", right, left),
+                    )
+                    .label(Severity::Warning, op_span, None::<String>),
+                );
+            }
+        }
     }
 }
 
-fn analyze_op(reporter: &Reporter, _span: Span, op: &Op, left: &Ast, right: &Ast) {
-    analyze_ast(reporter, left);
-    analyze_ast(reporter, right);
+/// Checks that the divisor of an integer division isn't 0. Folding catches divisors like
+/// `2 - 2` that are zero without being a literal 0. Only integers are folded, but a float
+/// dividend promotes the divisor, and dividing a float by 0 is fine.
+fn analyze_divisor<'a>(
+    reporter: &Reporter,
+    op: &Op,
+    left: &'a Ast,
+    right: &'a Ast,
+    scope: &mut Scope<'a>,
+    constants: &Constants,
+) -> usize {
+    let divides = *op == Op::Div || *op == Op::Mod;
 
-    if let Op::Div = op {
-        if let Ast::Int { span, val: 0 } = right {
+    if divides && constants.get(right) == Some(0) && matches!(infer(left, scope), Ok(Type::Int)) {
+        reporter.add(
+            Diagnostic::new(Severity::Error, None, "Cannot divide by 0").label(
+                Severity::Error,
+                right.span(),
+                None::<String>,
+            ),
+        );
+
+        1
+    } else {
+        0
+    }
+}
+
+/// Checks the negation of `expr`, which is checked separately.
+fn analyze_neg(
+    reporter: &Reporter,
+    span: Span,
+    expr: &Ast,
+    constants: &Constants,
+    mode: IntMode,
+) -> usize {
+    // Values are unsigned, so only 0 can be negated unless arithmetic wraps.
+    match constants.get(expr) {
+        Some(val) if val != 0 && mode.overflow == Overflow::Trap => {
             reporter.add(
-                Diagnostic::new(Severity::Error, None, "Cannot divide by 0").label(
+                Diagnostic::new(
+                    Severity::Error,
+                    None,
+                    format!("Cannot negate {}, integers are unsigned", val),
+                )
+                .label(Severity::Error, span, None::<String>),
+            );
+
+            1
+        }
+        _ => 0,
+    }
+}
+
+/// Computes the type `ast` evaluates to, or the first type error within it. Integers are
+/// promoted when mixed with floats. All names are expected to have been resolved already.
+pub fn infer<'a>(ast: &'a Ast, scope: &mut Scope<'a>) -> Result<Type, Diagnostic> {
+    crate::grow_stack(|| {
+        match ast {
+            Ast::Int { .. } => Ok(Type::Int),
+            Ast::Float { .. } => Ok(Type::Float),
+            Ast::Str { .. } => Ok(Type::Str),
+            // Syntax errors have already been reported, and keep `analyze` from getting here.
+            Ast::Error { .. } => Ok(Type::Int),
+            Ast::Op {
+                op, left, right, ..
+            } if op.is_bitwise() => {
+                expect_int(left, scope)?;
+                expect_int(right, scope)
+            }
+            Ast::Op {
+                op, left, right, ..
+            } => {
+                let left_ty = infer(left, scope)?;
+                let right_ty = infer(right, scope)?;
+
+                // `+` concatenates strings, they can't be mixed with numbers.
+                if *op == Op::Add && left_ty == Type::Str && right_ty == Type::Str {
+                    return Ok(Type::Str);
+                }
+
+                Ok(promote(number(left, left_ty)?, number(right, right_ty)?))
+            }
+            Ast::Cmp {
+                span,
+                op,
+                left,
+                right,
+            } => {
+                let left = infer(left, scope)?;
+                let right = infer(right, scope)?;
+
+                // Strings are compared by their characters.
+                let msg = if left != right
+                    && (left == Type::Bool
+                        || right == Type::Bool
+                        || left == Type::Str
+                        || right == Type::Str)
+                {
+                    format!("Cannot compare {} with {}", left, right)
+                } else if left == Type::Bool && *op != CmpOp::Eq && *op != CmpOp::Ne {
+                    "Booleans can only be compared for equality".to_string()
+                } else {
+                    return Ok(Type::Bool);
+                };
+
+                Err(Diagnostic::new(Severity::Error, None, msg).label(
                     Severity::Error,
                     *span,
                     None::<String>,
-                ),
-            );
+                ))
+            }
+            Ast::Neg { expr, .. } => expect_number(expr, scope),
+            Ast::Not { expr, .. } => expect_int(expr, scope),
+            Ast::Group { expr, .. } => infer(expr, scope),
+            Ast::Let {
+                name, value, body, ..
+            } => {
+                let value = infer(value, scope)?;
+
+                scope.push((name.as_str(), value));
+
+                let body = infer(body, scope);
+
+                scope.pop();
+                body
+            }
+            Ast::If {
+                span,
+                cond,
+                then,
+                else_,
+            } => {
+                let ty = infer(cond, scope)?;
+
+                if ty != Type::Bool {
+                    return Err(Diagnostic::new(
+                        Severity::Error,
+                        None,
+                        format!("Expected a boolean condition, found {}", ty),
+                    )
+                    .label(Severity::Error, cond.span(), None::<String>));
+                }
+
+                let then = infer(then, scope)?;
+                let else_ = infer(else_, scope)?;
+
+                if then == else_ {
+                    Ok(then)
+                } else {
+                    Err(Diagnostic::new(
+                        Severity::Error,
+                        None,
+                        format!(
+                            "The branches of `if` have different types: {} and {}",
+                            then, else_
+                        ),
+                    )
+                    .label(Severity::Error, *span, None::<String>))
+                }
+            }
+            Ast::Var { name, .. } => Ok(scope
+                .iter()
+                .rev()
+                .find(|(var, _)| *var == name.as_str())
+                .map_or(Type::Int, |(_, ty)| *ty)),
+            // Divisors only make sense for integers.
+            Ast::Call { name, args, .. } if name == "gcd" || name == "lcm" => args
+                .iter()
+                .try_fold(Type::Int, |_, arg| expect_int(arg, scope)),
+            // Conversions take any number.
+            Ast::Call { name, args, .. } if name == "int" || name == "float" => {
+                for arg in args {
+                    expect_number(arg, scope)?;
+                }
+
+                Ok(if name == "int" {
+                    Type::Int
+                } else {
+                    Type::Float
+                })
+            }
+            // Other built-in functions return the type of their arguments.
+            Ast::Call { args, .. } => args.iter().try_fold(Type::Int, |ty, arg| {
+                Ok(promote(ty, expect_number(arg, scope)?))
+            }),
+        }
+    })
+}
+
+/// Infers the type of `ast`, which must be a number.
+fn expect_number<'a>(ast: &'a Ast, scope: &mut Scope<'a>) -> Result<Type, Diagnostic> {
+    let ty = infer(ast, scope)?;
+
+    number(ast, ty)
+}
+
+/// Checks that `ty`, the type of `ast`, is a number.
+fn number(ast: &Ast, ty: Type) -> Result<Type, Diagnostic> {
+    match ty {
+        Type::Int | Type::Float => Ok(ty),
+        Type::Bool | Type::Str => Err(Diagnostic::new(
+            Severity::Error,
+            None,
+            format!("Expected a number, found {}", ty),
+        )
+        .label(Severity::Error, ast.span(), None::<String>)),
+    }
+}
+
+/// Infers the type of `ast`, which must be an integer.
+fn expect_int<'a>(ast: &'a Ast, scope: &mut Scope<'a>) -> Result<Type, Diagnostic> {
+    match infer(ast, scope)? {
+        Type::Int => Ok(Type::Int),
+        ty => Err(Diagnostic::new(
+            Severity::Error,
+            None,
+            format!("Expected an integer, found {}", ty),
+        )
+        .label(Severity::Error, ast.span(), None::<String>)),
+    }
+}
+
+fn promote(left: Type, right: Type) -> Type {
+    if left == Type::Float || right == Type::Float {
+        Type::Float
+    } else {
+        left
+    }
+}
+
+fn lint_parens(reporter: &Reporter, ast: &Ast, parent: Option<Parent>) {
+    crate::grow_stack(|| {
+        match ast {
+            Ast::Int { .. }
+            | Ast::Float { .. }
+            | Ast::Str { .. }
+            | Ast::Var { .. }
+            | Ast::Error { .. } => {}
+            Ast::Let { value, body, .. } => {
+                lint_parens(reporter, value, None);
+                lint_parens(reporter, body, None);
+            }
+            Ast::If {
+                cond, then, else_, ..
+            } => {
+                lint_parens(reporter, cond, None);
+                lint_parens(reporter, then, None);
+                lint_parens(reporter, else_, None);
+            }
+            Ast::Call { args, .. } => {
+                for arg in args {
+                    lint_parens(reporter, arg, None);
+                }
+            }
+            Ast::Op {
+                op, left, right, ..
+            } => {
+                lint_parens(reporter, left, Some(Parent::Binary(*op, Side::Left)));
+                lint_parens(reporter, right, Some(Parent::Binary(*op, Side::Right)));
+            }
+            Ast::Cmp { left, right, .. } => {
+                lint_parens(reporter, left, Some(Parent::Cmp));
+                lint_parens(reporter, right, Some(Parent::Cmp));
+            }
+            Ast::Neg { expr, .. } | Ast::Not { expr, .. } => {
+                lint_parens(reporter, expr, Some(Parent::Unary))
+            }
+            Ast::Group { span, expr } => {
+                let redundant = match (&**expr, parent) {
+                    (Ast::Op { op: inner, .. }, Some(Parent::Binary(outer, side))) => {
+                        // With equal precedence the group only matters on the side opposite
+                        // to the parent's associativity.
+                        inner.precedence() > outer.precedence()
+                            || inner.precedence() == outer.precedence()
+                                && match side {
+                                    Side::Left => !outer.right_associative(),
+                                    Side::Right => outer.right_associative(),
+                                }
+                    }
+                    // Only `**` binds tighter than negation.
+                    (Ast::Op { op: inner, .. }, Some(Parent::Unary)) => *inner == Op::Pow,
+                    // Bitwise operators bind looser than comparisons, arithmetic tighter.
+                    (Ast::Op { op: inner, .. }, Some(Parent::Cmp)) => !inner.is_bitwise(),
+                    // `-2 ** 2` negates the power, so a negated base must stay grouped.
+                    (Ast::Neg { .. }, Some(Parent::Binary(Op::Pow, Side::Left)))
+                    | (Ast::Not { .. }, Some(Parent::Binary(Op::Pow, Side::Left))) => false,
+                    // The body of a `let` or the `else` branch would swallow anything following
+                    // the group.
+                    (Ast::Let { .. }, Some(_)) | (Ast::If { .. }, Some(_)) => false,
+                    // Comparisons only bind tighter than the bitwise operators.
+                    (Ast::Cmp { .. }, Some(Parent::Binary(outer, _))) => outer.is_bitwise(),
+                    (Ast::Cmp { .. }, Some(_)) => false,
+                    _ => true,
+                };
+
+                if redundant {
+                    reporter.add(
+                        Diagnostic::new(Severity::Warning, None, "Redundant parentheses").label(
+                            Severity::Warning,
+                            *span,
+                            None::<String>,
+                        ),
+                    );
+                }
+
+                lint_parens(reporter, expr, parent);
+            }
+        }
+    })
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Int => write!(f, "an integer"),
+            Self::Float => write!(f, "a float"),
+            Self::Bool => write!(f, "a boolean"),
+            Self::Str => write!(f, "a string"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diagnostics::FileInterner;
+
+    fn analyzes(source: &str) -> bool {
+        crate::analyze(&FileInterner::new(), "<test>", source, Options::default()).is_ok()
+    }
+
+    #[test]
+    fn dividing_a_float_by_0_is_fine() {
+        assert!(analyzes("1.5 / 0"));
+        assert!(analyzes("let y = 1.5 in y / (2 - 2)"));
+        assert!(!analyzes("1 / 0"));
+        assert!(!analyzes("let x = 3 in x % (2 - 2)"));
+    }
+}
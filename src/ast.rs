@@ -1,4 +1,5 @@
-use diagnostics::Span;
+use diagnostics::{Position, Span, Spanned};
+use serde::ser::{Serialize, SerializeStruct, SerializeStructVariant, Serializer};
 use std::fmt;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -7,35 +8,439 @@ pub enum Ast {
         span: Span,
         val: u64,
     },
+    Float {
+        span: Span,
+        val: f64,
+    },
+    /// Strings can only be concatenated with `+`.
+    Str {
+        span: Span,
+        val: String,
+    },
     Op {
         span: Span,
         op: Op,
+        /// The span of the operator token alone.
+        op_span: Span,
+        left: Box<Ast>,
+        right: Box<Ast>,
+    },
+    /// A comparison, evaluating to a boolean.
+    Cmp {
+        span: Span,
+        op: CmpOp,
         left: Box<Ast>,
         right: Box<Ast>,
     },
+    /// Values are unsigned, so negation is `0 - expr`: it underflows for anything but 0,
+    /// unless arithmetic wraps and it produces the two's complement.
+    Neg {
+        span: Span,
+        expr: Box<Ast>,
+    },
+    /// The bitwise complement of an integer.
+    Not {
+        span: Span,
+        expr: Box<Ast>,
+    },
     Group {
         span: Span,
         expr: Box<Ast>,
     },
+    /// Binds `name` to `value` within `body`, shadowing any outer binding of the same name.
+    Let {
+        span: Span,
+        name: String,
+        value: Box<Ast>,
+        body: Box<Ast>,
+    },
+    /// Evaluates to `then` if `cond`, a boolean, is true and to `else_` otherwise. Both
+    /// branches have the same type.
+    If {
+        span: Span,
+        cond: Box<Ast>,
+        then: Box<Ast>,
+        else_: Box<Ast>,
+    },
+    Var {
+        span: Span,
+        name: String,
+    },
+    /// A call to one of the built-in functions.
+    Call {
+        span: Span,
+        name: String,
+        name_span: Span,
+        args: Vec<Ast>,
+    },
+    /// Stands in for what failed to parse, like the missing operand of `2 +`, so the rest of
+    /// the tree keeps its shape. The error has already been reported, and analysis rejects
+    /// programs containing one.
+    Error {
+        span: Span,
+    },
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Binary operators, the bitwise ones only apply to integers.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
 pub enum Op {
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub enum CmpOp {
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Spanned for Ast {
+    fn span(&self) -> Span {
+        match self {
+            Self::Int { span, .. }
+            | Self::Float { span, .. }
+            | Self::Str { span, .. }
+            | Self::Op { span, .. }
+            | Self::Cmp { span, .. }
+            | Self::Neg { span, .. }
+            | Self::Not { span, .. }
+            | Self::Group { span, .. }
+            | Self::Let { span, .. }
+            | Self::If { span, .. }
+            | Self::Var { span, .. }
+            | Self::Call { span, .. }
+            | Self::Error { span } => *span,
+        }
+    }
+}
+
+impl Ast {
+    /// Removes every `Group`, which only exist to give the parentheses a span. Precedence
+    /// is already encoded in the shape of the tree.
+    pub fn strip_groups(mut self) -> Ast {
+        self.strip_groups_in_place();
+        self
+    }
+
+    fn strip_groups_in_place(&mut self) {
+        crate::grow_stack(|| {
+            while let Self::Group { expr, .. } = self {
+                *self = take(expr);
+            }
+
+            match self {
+                Self::Int { .. }
+                | Self::Float { .. }
+                | Self::Str { .. }
+                | Self::Var { .. }
+                | Self::Error { .. }
+                | Self::Group { .. } => {}
+                Self::Op { left, right, .. } | Self::Cmp { left, right, .. } => {
+                    left.strip_groups_in_place();
+                    right.strip_groups_in_place();
+                }
+                Self::Neg { expr, .. } | Self::Not { expr, .. } => expr.strip_groups_in_place(),
+                Self::Let { value, body, .. } => {
+                    value.strip_groups_in_place();
+                    body.strip_groups_in_place();
+                }
+                Self::If {
+                    cond, then, else_, ..
+                } => {
+                    cond.strip_groups_in_place();
+                    then.strip_groups_in_place();
+                    else_.strip_groups_in_place();
+                }
+                Self::Call { args, .. } => {
+                    for arg in args {
+                        arg.strip_groups_in_place();
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// The derived drop would recurse as deep as the tree goes, so the children of every node
+/// are moved out and dropped through `grow_stack` instead.
+impl Drop for Ast {
+    fn drop(&mut self) {
+        crate::grow_stack(|| match self {
+            Self::Int { .. }
+            | Self::Float { .. }
+            | Self::Str { .. }
+            | Self::Var { .. }
+            | Self::Error { .. } => {}
+            Self::Op { left, right, .. } | Self::Cmp { left, right, .. } => {
+                drop(take(left));
+                drop(take(right));
+            }
+            Self::Neg { expr, .. } | Self::Not { expr, .. } | Self::Group { expr, .. } => {
+                drop(take(expr))
+            }
+            Self::Let { value, body, .. } => {
+                drop(take(value));
+                drop(take(body));
+            }
+            Self::If {
+                cond, then, else_, ..
+            } => {
+                drop(take(cond));
+                drop(take(then));
+                drop(take(else_));
+            }
+            Self::Call { args, .. } => drop(std::mem::take(args)),
+        })
+    }
+}
+
+/// Moves the node out of `ast`, leaving a literal behind.
+fn take(ast: &mut Ast) -> Ast {
+    std::mem::replace(
+        ast,
+        Ast::Int {
+            span: Span::default(),
+            val: 0,
+        },
+    )
+}
+
+impl Op {
+    /// Follows C, so the bitwise operators bind looser than comparisons, which bind looser
+    /// than arithmetic: `a & b == c` is `a & (b == c)`, from loosest to tightest the
+    /// order is `|`, `^`, `&`, comparisons, `+ -`, `* / %`, negation, `**`.
+    pub fn precedence(self) -> u8 {
+        match self {
+            Self::BitOr => 1,
+            Self::BitXor => 2,
+            Self::BitAnd => 3,
+            Self::Add | Self::Sub => 4,
+            Self::Mul | Self::Div | Self::Mod => 5,
+            Self::Pow => 6,
+        }
+    }
+
+    pub fn is_bitwise(self) -> bool {
+        matches!(self, Self::BitAnd | Self::BitOr | Self::BitXor)
+    }
+
+    pub fn right_associative(self) -> bool {
+        self == Self::Pow
+    }
+}
+
+/// Spans are written as `{start, end, file}` with positions as `{line, col, offset}` and the
+/// file by its name.
+impl Serialize for Ast {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::grow_stack(|| match self {
+            Self::Int { span, val } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 0, "Int", 2)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("val", val)?;
+                s.end()
+            }
+            Self::Float { span, val } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 1, "Float", 2)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("val", val)?;
+                s.end()
+            }
+            Self::Str { span, val } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 2, "Str", 2)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("val", val)?;
+                s.end()
+            }
+            Self::Op {
+                span,
+                op,
+                op_span,
+                left,
+                right,
+            } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 3, "Op", 5)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("op", op)?;
+                s.serialize_field("op_span", &SerSpan(*op_span))?;
+                s.serialize_field("left", left)?;
+                s.serialize_field("right", right)?;
+                s.end()
+            }
+            Self::Cmp {
+                span,
+                op,
+                left,
+                right,
+            } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 4, "Cmp", 4)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("op", op)?;
+                s.serialize_field("left", left)?;
+                s.serialize_field("right", right)?;
+                s.end()
+            }
+            Self::Neg { span, expr } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 5, "Neg", 2)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("expr", expr)?;
+                s.end()
+            }
+            Self::Not { span, expr } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 6, "Not", 2)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("expr", expr)?;
+                s.end()
+            }
+            Self::Group { span, expr } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 7, "Group", 2)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("expr", expr)?;
+                s.end()
+            }
+            Self::Let {
+                span,
+                name,
+                value,
+                body,
+            } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 8, "Let", 4)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("name", name)?;
+                s.serialize_field("value", value)?;
+                s.serialize_field("body", body)?;
+                s.end()
+            }
+            Self::If {
+                span,
+                cond,
+                then,
+                else_,
+            } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 9, "If", 4)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("cond", cond)?;
+                s.serialize_field("then", then)?;
+                s.serialize_field("else", else_)?;
+                s.end()
+            }
+            Self::Var { span, name } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 10, "Var", 2)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("name", name)?;
+                s.end()
+            }
+            Self::Call {
+                span,
+                name,
+                name_span,
+                args,
+            } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 11, "Call", 4)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.serialize_field("name", name)?;
+                s.serialize_field("name_span", &SerSpan(*name_span))?;
+                s.serialize_field("args", args)?;
+                s.end()
+            }
+            Self::Error { span } => {
+                let mut s = serializer.serialize_struct_variant("Ast", 12, "Error", 1)?;
+
+                s.serialize_field("span", &SerSpan(*span))?;
+                s.end()
+            }
+        })
+    }
+}
+
+/// `Span` and `Position` belong to the diagnostics crate, so they are serialized through
+/// these wrappers.
+struct SerSpan(Span);
+
+struct SerPosition(Position);
+
+impl Serialize for SerSpan {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Span", 3)?;
+
+        s.serialize_field("start", &SerPosition(self.0.start))?;
+        s.serialize_field("end", &SerPosition(self.0.end))?;
+        s.serialize_field("file", self.0.file.name.as_str())?;
+        s.end()
+    }
+}
+
+impl Serialize for SerPosition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Position", 3)?;
+
+        s.serialize_field("line", &self.0.line)?;
+        s.serialize_field("col", &self.0.col)?;
+        s.serialize_field("offset", &self.0.offset)?;
+        s.end()
+    }
 }
 
 impl fmt::Display for Ast {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
+        crate::grow_stack(|| match self {
             Self::Int { val, .. } => write!(f, "{}", val),
+            Self::Float { val, .. } => write!(f, "{:?}", val),
+            Self::Str { val, .. } => write!(f, "{:?}", val),
             Self::Op {
                 op, left, right, ..
             } => write!(f, "{} {} {}", left, op, right),
+            Self::Cmp {
+                op, left, right, ..
+            } => write!(f, "{} {} {}", left, op, right),
+            Self::Neg { expr, .. } => write!(f, "-{}", expr),
+            Self::Not { expr, .. } => write!(f, "~{}", expr),
             Self::Group { expr, .. } => write!(f, "({})", expr),
-        }
+            Self::Let {
+                name, value, body, ..
+            } => write!(f, "let {} = {} in {}", name, value, body),
+            Self::If {
+                cond, then, else_, ..
+            } => write!(f, "if {} then {} else {}", cond, then, else_),
+            Self::Var { name, .. } => write!(f, "{}", name),
+            Self::Call { name, args, .. } => {
+                write!(f, "{}(", name)?;
+
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}", arg)?;
+                }
+
+                write!(f, ")")
+            }
+            Self::Error { .. } => write!(f, "<error>"),
+        })
     }
 }
 
@@ -46,6 +451,24 @@ impl fmt::Display for Op {
             Self::Sub => write!(f, "-"),
             Self::Mul => write!(f, "*"),
             Self::Div => write!(f, "/"),
+            Self::Mod => write!(f, "%"),
+            Self::Pow => write!(f, "**"),
+            Self::BitAnd => write!(f, "&"),
+            Self::BitOr => write!(f, "|"),
+            Self::BitXor => write!(f, "^"),
+        }
+    }
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Lt => write!(f, "<"),
+            Self::Gt => write!(f, ">"),
+            Self::Le => write!(f, "<="),
+            Self::Ge => write!(f, ">="),
+            Self::Eq => write!(f, "=="),
+            Self::Ne => write!(f, "!="),
         }
     }
 }
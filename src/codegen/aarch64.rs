@@ -0,0 +1,485 @@
+//! Emits AArch64 machine code for the Linux system call interface.
+//!
+//! Like the x86-64 backend this is a stack machine, every value occupies a 16 byte
+//! slot so that `sp` stays aligned as required by the architecture.
+
+use super::{lookup, unsupported_call, unsupported_float, unsupported_string, Code, Stack, Trap};
+use crate::ast::*;
+use crate::run::{IntMode, Overflow};
+use diagnostics::{Diagnostic, Severity, Spanned};
+
+pub fn gen_program(program: &[Ast], code: &mut Code, mode: IntMode) -> Result<(), Diagnostic> {
+    for (i, ast) in program.iter().enumerate() {
+        gen_ast(ast, code, &mut Vec::new(), mode)?;
+
+        let start = code.len();
+
+        gen_print_u64(code);
+
+        // Only the last result is kept for the exit status.
+        if i + 1 < program.len() {
+            inst(code, 0x910043ff, "add sp, sp, #16");
+        }
+
+        code.mark(start, ast.span());
+    }
+
+    if program.is_empty() {
+        mov_x0(code, 0);
+        push_x0(code);
+    }
+
+    gen_exit(code);
+
+    gen_traps(code);
+
+    Ok(())
+}
+
+fn inst(code: &mut Code, inst: u32, asm: &str) {
+    code.inst(&inst.to_le_bytes(), asm);
+}
+
+fn push_x0(code: &mut Code) {
+    inst(code, 0xf81f0fe0, "str x0, [sp, #-16]!");
+}
+
+fn pop_x0(code: &mut Code) {
+    inst(code, 0xf84107e0, "ldr x0, [sp], #16");
+}
+
+fn pop_x1(code: &mut Code) {
+    inst(code, 0xf84107e1, "ldr x1, [sp], #16");
+}
+
+/// Loads a 64-bit immediate into `x0` with a `movz` followed by three `movk`s.
+fn mov_x0(code: &mut Code, val: u64) {
+    let imm = (val & 0xffff) as u32;
+
+    inst(
+        code,
+        0xd2800000 | imm << 5,
+        &format!("movz x0, #{:#x}", imm),
+    );
+
+    for hw in 1..4 {
+        let imm = ((val >> (hw * 16)) & 0xffff) as u32;
+
+        inst(
+            code,
+            0xf2800000 | hw << 21 | imm << 5,
+            &format!("movk x0, #{:#x}, lsl #{}", imm, hw * 16),
+        );
+    }
+}
+
+/// Writes the value on top of the stack to stdout in decimal, followed by a newline, leaving
+/// the value on the stack.
+fn gen_print_u64(code: &mut Code) {
+    inst(code, 0xf94003e0, "ldr x0, [sp]");
+    inst(code, 0xd10083ff, "sub sp, sp, #32");
+    inst(code, 0x910083e1, "add x1, sp, #32");
+    inst(code, 0x52800142, "mov w2, #10");
+    inst(code, 0x381ffc22, "strb w2, [x1, #-1]!");
+    inst(code, 0xd2800143, "mov x3, #10");
+    code.label("1");
+    inst(code, 0x9ac30804, "udiv x4, x0, x3");
+    inst(code, 0x9b038085, "msub x5, x4, x3, x0");
+    inst(code, 0x1100c0a5, "add w5, w5, #48");
+    inst(code, 0x381ffc25, "strb w5, [x1, #-1]!");
+    inst(code, 0xaa0403e0, "mov x0, x4");
+    inst(code, 0xb5ffff60, "cbnz x0, 1b");
+    inst(code, 0x910083e2, "add x2, sp, #32");
+    inst(code, 0xcb010042, "sub x2, x2, x1");
+    inst(code, 0xd2800020, "mov x0, #1");
+    inst(code, 0xd2800808, "mov x8, #64");
+    inst(code, 0xd4000001, "svc #0");
+    inst(code, 0x910083ff, "add sp, sp, #32");
+}
+
+/// Pops the result off the stack and exits with its low byte as the status.
+fn gen_exit(code: &mut Code) {
+    pop_x0(code);
+    inst(code, 0x92401c00, "and x0, x0, #0xff");
+    inst(code, 0xd2800ba8, "mov x8, #93");
+    inst(code, 0xd4000001, "svc #0");
+}
+
+/// Truncates `x0` to the integer width.
+fn gen_mask(code: &mut Code, mode: IntMode) {
+    match mode.width {
+        8 => inst(code, 0x92401c00, "and x0, x0, #0xff"),
+        16 => inst(code, 0x92403c00, "and x0, x0, #0xffff"),
+        32 => inst(code, 0x92407c00, "and x0, x0, #0xffffffff"),
+        _ => {}
+    }
+}
+
+/// Emits `branch`, a `cbz`, `cbnz` or `b.cond` to the routine for `trap`. All of them encode
+/// the offset in bits 5 to 23, which are filled in by `gen_traps`.
+fn gen_trap_branch(code: &mut Code, trap: Trap, branch: u32, asm: &str) {
+    code.traps.push((code.len(), trap));
+    inst(code, branch, &format!("{} {}", asm, trap.label()));
+}
+
+/// Branches to the division by zero routine if `x1`, the divisor, is 0. `udiv` doesn't
+/// trap but produces 0, so this is needed for the same behaviour as on x86-64.
+fn gen_check_divisor(code: &mut Code) {
+    gen_trap_branch(code, Trap::DivisionByZero, 0xb4000001, "cbz x1,");
+}
+
+/// Whether 64-bit arithmetic has to detect overflow itself, through the flags or the upper
+/// half of a product.
+fn checks_64(mode: IntMode) -> bool {
+    mode.overflow == Overflow::Trap && mode.width >= 64
+}
+
+/// Branches to the overflow routine if the result in `x0` doesn't fit in a narrower width
+/// when trapping. Results are computed in 64 bits, so they fit if their upper bits are clear.
+fn gen_check_overflow(code: &mut Code, mode: IntMode) {
+    if mode.overflow == Overflow::Trap && mode.width < 64 {
+        inst(
+            code,
+            0xd340fc02 | mode.width << 16,
+            &format!("lsr x2, x0, #{}", mode.width),
+        );
+        gen_trap_branch(code, Trap::Overflow, 0xb5000002, "cbnz x2,");
+    }
+}
+
+/// Raises `x0` to the power of `x1` by squaring, leaving the result in `x0` and branching to
+/// the overflow routine if it doesn't fit. As in the x86-64 backend, the base is only
+/// squared while bits of the exponent remain.
+fn gen_checked_pow(code: &mut Code, mode: IntMode) {
+    inst(code, 0xaa0003e4, "mov x4, x0");
+    inst(code, 0xd2800023, "mov x3, #1");
+
+    let start = code.len();
+
+    code.label("1");
+
+    let tbz = code.len();
+
+    inst(code, 0x36000001, "tbz x1, #0, 2f");
+
+    if checks_64(mode) {
+        inst(code, 0x9bc47c62, "umulh x2, x3, x4");
+        gen_trap_branch(code, Trap::Overflow, 0xb5000002, "cbnz x2,");
+    }
+
+    inst(code, 0x9b047c60, "mul x0, x3, x4");
+    gen_check_overflow(code, mode);
+    inst(code, 0xaa0003e3, "mov x3, x0");
+    patch_branch(code, tbz, 0x3fff << 5, 5);
+    code.label("2");
+    inst(code, 0xd341fc21, "lsr x1, x1, #1");
+
+    let cbz = code.len();
+
+    inst(code, 0xb4000001, "cbz x1, 3f");
+
+    if checks_64(mode) {
+        inst(code, 0x9bc47c82, "umulh x2, x4, x4");
+        gen_trap_branch(code, Trap::Overflow, 0xb5000002, "cbnz x2,");
+    }
+
+    inst(code, 0x9b047c80, "mul x0, x4, x4");
+    gen_check_overflow(code, mode);
+    inst(code, 0xaa0003e4, "mov x4, x0");
+
+    let offset = ((start as i32 - code.len() as i32) / 4) as u32 & 0x3ffffff;
+
+    inst(code, 0x14000000 | offset, "b 1b");
+    patch_branch(code, cbz, 0x7ffff << 5, 5);
+    code.label("3");
+    inst(code, 0xaa0303e0, "mov x0, x3");
+}
+
+/// Points the branch at `at` to the current end of the code, its offset in words being
+/// stored in the bits of `field` starting at `shift`.
+fn patch_branch(code: &mut Code, at: usize, field: u32, shift: u32) {
+    let offset = ((code.len() as i32 - at as i32) / 4) as u32;
+    let mut word = [0; 4];
+
+    word.copy_from_slice(&code.bytes[at..at + 4]);
+
+    let word = u32::from_le_bytes(word) & !field | (offset << shift) & field;
+
+    code.bytes[at..at + 4].copy_from_slice(&word.to_le_bytes());
+}
+
+/// Places the routine of every trap branched to, pointing the branches at it.
+fn gen_traps(code: &mut Code) {
+    for routine in code.trap_routines() {
+        let start = code.len();
+
+        for &branch in &routine.branches {
+            let imm19 = ((start as i32 - branch as i32) / 4) as u32 & 0x7ffff;
+            let mut word = [0; 4];
+
+            word.copy_from_slice(&code.bytes[branch..branch + 4]);
+
+            let word = u32::from_le_bytes(word) & !(0x7ffff << 5) | imm19 << 5;
+
+            code.bytes[branch..branch + 4].copy_from_slice(&word.to_le_bytes());
+        }
+
+        let msg = &routine.message;
+
+        code.label(&routine.label);
+        inst(code, 0xd2800040, "mov x0, #2");
+        inst(code, 0x100000e1, &format!("adr x1, {}_msg", routine.label));
+        inst(
+            code,
+            0xd2800002 | (msg.len() as u32) << 5,
+            &format!("mov x2, #{}", msg.len()),
+        );
+        inst(code, 0xd2800808, "mov x8, #64");
+        inst(code, 0xd4000001, "svc #0");
+        inst(code, 0xd2800020, "mov x0, #1");
+        inst(code, 0xd2800ba8, "mov x8, #93");
+        inst(code, 0xd4000001, "svc #0");
+        code.label(&format!("{}_msg", routine.label));
+        code.inst(msg.as_bytes(), format!(".ascii {:?}", msg));
+
+        // The next routine's instructions must be aligned.
+        let padding = (4 - code.len() % 4) % 4;
+
+        if padding > 0 {
+            code.inst(&vec![0; padding], ".balign 4");
+        }
+    }
+}
+
+fn gen_ast<'a>(
+    ast: &'a Ast,
+    code: &mut Code,
+    stack: &mut Stack<'a>,
+    mode: IntMode,
+) -> Result<(), Diagnostic> {
+    crate::grow_stack(|| {
+        match ast {
+            Ast::Int { span, val } => {
+                let start = code.len();
+
+                mov_x0(code, *val);
+                push_x0(code);
+                code.mark(start, *span);
+            }
+            Ast::Float { span, .. } => return Err(unsupported_float(*span)),
+            Ast::Str { span, .. } => return Err(unsupported_string(*span)),
+            Ast::Call { span, .. } => return Err(unsupported_call(*span)),
+            Ast::Error { .. } => unreachable!("analysis rejects programs with syntax errors"),
+            Ast::Op {
+                span,
+                op,
+                left,
+                right,
+                ..
+            } => {
+                gen_ast(left, code, stack, mode)?;
+                stack.push(None);
+                gen_ast(right, code, stack, mode)?;
+                stack.pop();
+
+                let start = code.len();
+
+                pop_x1(code);
+                pop_x0(code);
+
+                match op {
+                    // Unsigned overflow sets the carry flag, a borrow clears it.
+                    Op::Add if checks_64(mode) => {
+                        inst(code, 0xab010000, "adds x0, x0, x1");
+                        gen_trap_branch(code, Trap::Overflow, 0x54000002, "b.hs");
+                    }
+                    Op::Sub if checks_64(mode) => {
+                        inst(code, 0xeb010000, "subs x0, x0, x1");
+                        gen_trap_branch(code, Trap::Overflow, 0x54000003, "b.lo");
+                    }
+                    Op::Mul if checks_64(mode) => {
+                        inst(code, 0x9bc17c02, "umulh x2, x0, x1");
+                        gen_trap_branch(code, Trap::Overflow, 0xb5000002, "cbnz x2,");
+                        inst(code, 0x9b017c00, "mul x0, x0, x1");
+                    }
+                    Op::Add => inst(code, 0x8b010000, "add x0, x0, x1"),
+                    Op::Sub => inst(code, 0xcb010000, "sub x0, x0, x1"),
+                    Op::Mul => inst(code, 0x9b017c00, "mul x0, x0, x1"),
+                    Op::Div => {
+                        gen_check_divisor(code);
+                        inst(code, 0x9ac10800, "udiv x0, x0, x1");
+                    }
+                    Op::Mod => {
+                        gen_check_divisor(code);
+                        inst(code, 0x9ac10802, "udiv x2, x0, x1");
+                        inst(code, 0x9b018040, "msub x0, x2, x1, x0");
+                    }
+                    Op::Pow if mode.overflow == Overflow::Trap => gen_checked_pow(code, mode),
+                    // Exponentiation by squaring, as in the x86-64 backend.
+                    Op::Pow => {
+                        inst(code, 0xd2800022, "mov x2, #1");
+                        code.label("1");
+                        inst(code, 0x36000041, "tbz x1, #0, 2f");
+                        inst(code, 0x9b007c42, "mul x2, x2, x0");
+                        code.label("2");
+                        inst(code, 0x9b007c00, "mul x0, x0, x0");
+                        inst(code, 0xd341fc21, "lsr x1, x1, #1");
+                        inst(code, 0xb5ffff81, "cbnz x1, 1b");
+                        inst(code, 0xaa0203e0, "mov x0, x2");
+                    }
+                    Op::BitAnd => inst(code, 0x8a010000, "and x0, x0, x1"),
+                    Op::BitOr => inst(code, 0xaa010000, "orr x0, x0, x1"),
+                    Op::BitXor => inst(code, 0xca010000, "eor x0, x0, x1"),
+                }
+
+                // Only these can produce a result that doesn't fit. Exponentiation checks its
+                // multiplications itself.
+                if let Op::Add | Op::Sub | Op::Mul = op {
+                    gen_check_overflow(code, mode);
+                }
+
+                if let Op::Add | Op::Sub | Op::Mul | Op::Pow = op {
+                    gen_mask(code, mode);
+                }
+
+                push_x0(code);
+                code.mark(start, *span);
+            }
+            Ast::Cmp {
+                span,
+                op,
+                left,
+                right,
+            } => {
+                gen_ast(left, code, stack, mode)?;
+                stack.push(None);
+                gen_ast(right, code, stack, mode)?;
+                stack.pop();
+
+                let start = code.len();
+
+                pop_x1(code);
+                pop_x0(code);
+                inst(code, 0xeb01001f, "cmp x0, x1");
+
+                // Values are unsigned, so the lower/higher conditions are used.
+                match op {
+                    CmpOp::Lt => inst(code, 0x9a9f27e0, "cset x0, lo"),
+                    CmpOp::Gt => inst(code, 0x9a9f97e0, "cset x0, hi"),
+                    CmpOp::Le => inst(code, 0x9a9f87e0, "cset x0, ls"),
+                    CmpOp::Ge => inst(code, 0x9a9f37e0, "cset x0, hs"),
+                    CmpOp::Eq => inst(code, 0x9a9f17e0, "cset x0, eq"),
+                    CmpOp::Ne => inst(code, 0x9a9f07e0, "cset x0, ne"),
+                }
+
+                push_x0(code);
+                code.mark(start, *span);
+            }
+            Ast::Neg { span, expr } => {
+                gen_ast(expr, code, stack, mode)?;
+
+                let start = code.len();
+
+                pop_x0(code);
+
+                // Negation is `0 - x0`, so it borrows for anything but 0.
+                if checks_64(mode) {
+                    inst(code, 0xeb0003e0, "negs x0, x0");
+                    gen_trap_branch(code, Trap::Overflow, 0x54000003, "b.lo");
+                } else {
+                    inst(code, 0xcb0003e0, "neg x0, x0");
+                    gen_check_overflow(code, mode);
+                }
+
+                gen_mask(code, mode);
+                push_x0(code);
+                code.mark(start, *span);
+            }
+            Ast::Not { span, expr } => {
+                gen_ast(expr, code, stack, mode)?;
+
+                let start = code.len();
+
+                pop_x0(code);
+                inst(code, 0xaa2003e0, "mvn x0, x0");
+                gen_mask(code, mode);
+                push_x0(code);
+                code.mark(start, *span);
+            }
+            Ast::Group { expr, .. } => gen_ast(expr, code, stack, mode)?,
+            Ast::Let {
+                span,
+                name,
+                value,
+                body,
+            } => {
+                gen_ast(value, code, stack, mode)?;
+                stack.push(Some(name.as_str()));
+                gen_ast(body, code, stack, mode)?;
+                stack.pop();
+
+                // Replace the binding with the result of the body.
+                let start = code.len();
+
+                pop_x0(code);
+                inst(code, 0xf90003e0, "str x0, [sp]");
+                code.mark(start, *span);
+            }
+            // Each branch leaves its result where the condition was.
+            Ast::If {
+                span,
+                cond,
+                then,
+                else_,
+            } => {
+                gen_ast(cond, code, stack, mode)?;
+
+                let (else_label, end_label) = code.conditional_labels();
+                let start = code.len();
+
+                pop_x0(code);
+
+                let cbz = code.len();
+
+                inst(code, 0xb4000000, &format!("cbz x0, {}", else_label));
+                code.mark(start, *span);
+                gen_ast(then, code, stack, mode)?;
+
+                let b = code.len();
+
+                inst(code, 0x14000000, &format!("b {}", end_label));
+                code.mark(b, *span);
+                patch_branch(code, cbz, 0x7ffff << 5, 5);
+                code.label(&else_label);
+                gen_ast(else_, code, stack, mode)?;
+                patch_branch(code, b, 0x3ffffff, 0);
+                code.label(&end_label);
+            }
+            Ast::Var { span, name } => {
+                let offset = lookup(stack, name) * 16;
+
+                // `ldr` encodes the offset in 12 bits, scaled by 8.
+                if offset / 8 > 0xfff {
+                    return Err(Diagnostic::new(
+                        Severity::Error,
+                        None,
+                        "Variable is too far up the stack to be loaded",
+                    )
+                    .label(Severity::Error, *span, None::<String>));
+                }
+
+                let start = code.len();
+
+                inst(
+                    code,
+                    0xf94003e0 | ((offset / 8) as u32) << 10,
+                    &format!("ldr x0, [sp, #{}]", offset),
+                );
+                push_x0(code);
+                code.mark(start, *span);
+            }
+        }
+
+        Ok(())
+    })
+}
@@ -0,0 +1,129 @@
+use crate::ast::*;
+use crate::constants::Constants;
+use diagnostics::Spanned;
+
+/// Copies `ast`, collapsing the subexpressions found in `constants` into a single `Ast::Int`.
+pub fn fold(ast: &Ast, constants: &Constants) -> Ast {
+    crate::grow_stack(|| {
+        if let Some(val) = constants.get(ast) {
+            return Ast::Int {
+                span: ast.span(),
+                val,
+            };
+        }
+
+        match ast {
+            Ast::Int { .. }
+            | Ast::Float { .. }
+            | Ast::Str { .. }
+            | Ast::Var { .. }
+            | Ast::Error { .. } => ast.clone(),
+            Ast::Op {
+                span,
+                op,
+                op_span,
+                left,
+                right,
+            } => Ast::Op {
+                span: *span,
+                op: *op,
+                op_span: *op_span,
+                left: Box::new(fold(left, constants)),
+                right: Box::new(fold(right, constants)),
+            },
+            Ast::Cmp {
+                span,
+                op,
+                left,
+                right,
+            } => Ast::Cmp {
+                span: *span,
+                op: *op,
+                left: Box::new(fold(left, constants)),
+                right: Box::new(fold(right, constants)),
+            },
+            Ast::Neg { span, expr } => Ast::Neg {
+                span: *span,
+                expr: Box::new(fold(expr, constants)),
+            },
+            Ast::Not { span, expr } => Ast::Not {
+                span: *span,
+                expr: Box::new(fold(expr, constants)),
+            },
+            Ast::Group { span, expr } => Ast::Group {
+                span: *span,
+                expr: Box::new(fold(expr, constants)),
+            },
+            Ast::Let {
+                span,
+                name,
+                value,
+                body,
+            } => Ast::Let {
+                span: *span,
+                name: name.clone(),
+                value: Box::new(fold(value, constants)),
+                body: Box::new(fold(body, constants)),
+            },
+            Ast::If {
+                span,
+                cond,
+                then,
+                else_,
+            } => Ast::If {
+                span: *span,
+                cond: Box::new(fold(cond, constants)),
+                then: Box::new(fold(then, constants)),
+                else_: Box::new(fold(else_, constants)),
+            },
+            Ast::Call {
+                span,
+                name,
+                name_span,
+                args,
+            } => Ast::Call {
+                span: *span,
+                name: name.clone(),
+                name_span: *name_span,
+                args: args.iter().map(|arg| fold(arg, constants)).collect(),
+            },
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_test;
+    use crate::run::IntMode;
+
+    fn fold_str(source: &str) -> Ast {
+        let program = parse_test(source).expect("syntax error");
+
+        fold(&program[0], &Constants::find(&program, IntMode::default()))
+    }
+
+    #[test]
+    fn folds_nested_constants() {
+        assert!(matches!(fold_str("2*(3+4)"), Ast::Int { val: 14, .. }));
+        assert!(matches!(
+            fold_str("let x = 2 in x * 3"),
+            Ast::Int { val: 6, .. }
+        ));
+    }
+
+    #[test]
+    fn let_shadows_outer_constant() {
+        // The inner `x` isn't constant, the outer one is again once the inner `let` ends.
+        match &fold_str("let x = 1 in (let x = y in x) + x") {
+            Ast::Let { body, .. } => match &**body {
+                Ast::Op { left, right, .. } => {
+                    assert_eq!(left.to_string(), "(let x = y in x)");
+                    assert!(matches!(**right, Ast::Int { val: 1, .. }));
+                }
+                ast => panic!("folded to {}", ast),
+            },
+            ast => panic!("folded to {}", ast),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Emits textual LLVM IR defining `main`, which returns the result of the last expression.
+//!
+//! Every node becomes an SSA value. Values are named `%t0`, `%t1`, ... and blocks `L0`,
+//! `L1`, ... since unnamed ones would have to be numbered in a single sequence. Failures
+//! branch to a block calling `llvm.trap`.
+
+use super::{unsupported_call, unsupported_float, unsupported_string};
+use crate::ast::*;
+use crate::run::{IntMode, Overflow};
+use diagnostics::Diagnostic;
+use std::fmt::Write;
+
+/// The values bound by `let`, innermost last.
+type Env<'a> = Vec<(&'a str, String)>;
+
+/// The body of `main` under construction.
+#[derive(Default)]
+struct Function {
+    body: String,
+    temps: usize,
+    blocks: usize,
+    /// The block instructions are currently added to, which `phi`s refer to.
+    block: String,
+    /// Whether anything branches to the trap block.
+    traps: bool,
+    /// The overflow checking intrinsics called, by operation.
+    intrinsics: Vec<&'static str>,
+    pow: bool,
+}
+
+impl Function {
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("%t{}", self.temps - 1)
+    }
+
+    fn new_block(&mut self) -> String {
+        self.blocks += 1;
+        format!("L{}", self.blocks - 1)
+    }
+
+    fn inst(&mut self, inst: impl AsRef<str>) {
+        writeln!(self.body, "  {}", inst.as_ref()).unwrap();
+    }
+
+    /// Assigns the result of `inst` to a new value and returns it.
+    fn value(&mut self, inst: impl AsRef<str>) -> String {
+        let temp = self.temp();
+
+        self.inst(format!("{} = {}", temp, inst.as_ref()));
+        temp
+    }
+
+    fn label(&mut self, block: String) {
+        writeln!(self.body, "{}:", block).unwrap();
+        self.block = block;
+    }
+
+    /// Branches to the trap block if the `i1` `cond` is true.
+    fn trap_if(&mut self, cond: &str) {
+        let next = self.new_block();
+
+        self.inst(format!("br i1 {}, label %trap, label %{}", cond, next));
+        self.label(next);
+        self.traps = true;
+    }
+}
+
+pub fn gen_program(program: &[Ast], mode: IntMode) -> Result<String, Diagnostic> {
+    let mut function = Function::default();
+    let mut result = "0".to_string();
+
+    function.label("entry".to_string());
+
+    for ast in program {
+        result = gen_ast(ast, &mut function, &mut Vec::new(), mode)?;
+    }
+
+    function.inst(format!("ret i64 {}", result));
+
+    if function.traps {
+        writeln!(function.body, "trap:").unwrap();
+        function.inst("call void @llvm.trap()");
+        function.inst("unreachable");
+    }
+
+    let mut out = String::new();
+
+    for op in &function.intrinsics {
+        writeln!(
+            out,
+            "declare {{i64, i1}} @llvm.{}.with.overflow.i64(i64, i64)",
+            op
+        )
+        .unwrap();
+    }
+
+    if function.traps {
+        out.push_str("declare void @llvm.trap()\n");
+    }
+
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    if function.pow {
+        out.push_str(POW);
+        out.push('\n');
+    }
+
+    write!(out, "define i64 @main() {{\n{}}}\n", function.body).unwrap();
+
+    Ok(out)
+}
+
+/// Exponentiation by squaring, as in the machine code backends.
+const POW: &str = "\
+define internal i64 @pow(i64 %base, i64 %exp) {
+entry:
+  br label %loop
+loop:
+  %result = phi i64 [ 1, %entry ], [ %result.next, %body ]
+  %b = phi i64 [ %base, %entry ], [ %b.next, %body ]
+  %e = phi i64 [ %exp, %entry ], [ %e.next, %body ]
+  %done = icmp eq i64 %e, 0
+  br i1 %done, label %exit, label %body
+body:
+  %bit = and i64 %e, 1
+  %odd = icmp ne i64 %bit, 0
+  %product = mul i64 %result, %b
+  %result.next = select i1 %odd, i64 %product, i64 %result
+  %b.next = mul i64 %b, %b
+  %e.next = lshr i64 %e, 1
+  br label %loop
+exit:
+  ret i64 %result
+}
+";
+
+/// Truncates `val` to the integer width.
+fn gen_mask(function: &mut Function, val: String, mode: IntMode) -> String {
+    if mode.width < 64 {
+        function.value(format!("and i64 {}, {}", val, mode.mask(u64::max_value())))
+    } else {
+        val
+    }
+}
+
+/// Applies `op`, which is `add`, `sub` or `mul`, trapping on overflow unless wrapping. At
+/// 64 bits the overflow intrinsics detect it, narrower results are computed in 64 bits and
+/// fit if they don't exceed the maximum.
+fn gen_arith(
+    function: &mut Function,
+    op: &'static str,
+    left: &str,
+    right: &str,
+    mode: IntMode,
+) -> String {
+    if mode.overflow == Overflow::Wrap {
+        return function.value(format!("{} i64 {}, {}", op, left, right));
+    }
+
+    if mode.width < 64 {
+        let result = function.value(format!("{} i64 {}, {}", op, left, right));
+        let overflow = function.value(format!("icmp ugt i64 {}, {}", result, mode.max()));
+
+        function.trap_if(&overflow);
+        return result;
+    }
+
+    let intrinsic = match op {
+        "add" => "uadd",
+        "sub" => "usub",
+        _ => "umul",
+    };
+
+    if !function.intrinsics.contains(&intrinsic) {
+        function.intrinsics.push(intrinsic);
+    }
+
+    let pair = function.value(format!(
+        "call {{i64, i1}} @llvm.{}.with.overflow.i64(i64 {}, i64 {})",
+        intrinsic, left, right
+    ));
+    let result = function.value(format!("extractvalue {{i64, i1}} {}, 0", pair));
+    let overflow = function.value(format!("extractvalue {{i64, i1}} {}, 1", pair));
+
+    function.trap_if(&overflow);
+    result
+}
+
+/// Division by zero is undefined behaviour in LLVM, so the divisor is checked first.
+fn gen_div(function: &mut Function, op: &str, left: &str, right: &str) -> String {
+    let zero = function.value(format!("icmp eq i64 {}, 0", right));
+
+    function.trap_if(&zero);
+    function.value(format!("{} i64 {}, {}", op, left, right))
+}
+
+/// Returns the value `ast` evaluates to, either a constant or an SSA value.
+fn gen_ast<'a>(
+    ast: &'a Ast,
+    function: &mut Function,
+    env: &mut Env<'a>,
+    mode: IntMode,
+) -> Result<String, Diagnostic> {
+    crate::grow_stack(|| {
+        Ok(match ast {
+            // Constants are signed, integers above `i64::MAX` are written as their two's
+            // complement.
+            Ast::Int { val, .. } => (*val as i64).to_string(),
+            Ast::Float { span, .. } => return Err(unsupported_float(*span)),
+            Ast::Str { span, .. } => return Err(unsupported_string(*span)),
+            Ast::Call { span, .. } => return Err(unsupported_call(*span)),
+            Ast::Error { .. } => unreachable!("analysis rejects programs with syntax errors"),
+            Ast::Op {
+                op, left, right, ..
+            } => {
+                let left = gen_ast(left, function, env, mode)?;
+                let right = gen_ast(right, function, env, mode)?;
+
+                match op {
+                    Op::Add => {
+                        let result = gen_arith(function, "add", &left, &right, mode);
+
+                        gen_mask(function, result, mode)
+                    }
+                    Op::Sub => {
+                        let result = gen_arith(function, "sub", &left, &right, mode);
+
+                        gen_mask(function, result, mode)
+                    }
+                    Op::Mul => {
+                        let result = gen_arith(function, "mul", &left, &right, mode);
+
+                        gen_mask(function, result, mode)
+                    }
+                    Op::Div => gen_div(function, "udiv", &left, &right),
+                    Op::Mod => gen_div(function, "urem", &left, &right),
+                    // Overflow isn't detected, as in the machine code backends.
+                    Op::Pow => {
+                        function.pow = true;
+
+                        let result =
+                            function.value(format!("call i64 @pow(i64 {}, i64 {})", left, right));
+
+                        gen_mask(function, result, mode)
+                    }
+                    Op::BitAnd => function.value(format!("and i64 {}, {}", left, right)),
+                    Op::BitOr => function.value(format!("or i64 {}, {}", left, right)),
+                    Op::BitXor => function.value(format!("xor i64 {}, {}", left, right)),
+                }
+            }
+            Ast::Cmp {
+                op, left, right, ..
+            } => {
+                let left = gen_ast(left, function, env, mode)?;
+                let right = gen_ast(right, function, env, mode)?;
+                // Values are unsigned, so the unsigned conditions are used.
+                let cond = match op {
+                    CmpOp::Lt => "ult",
+                    CmpOp::Gt => "ugt",
+                    CmpOp::Le => "ule",
+                    CmpOp::Ge => "uge",
+                    CmpOp::Eq => "eq",
+                    CmpOp::Ne => "ne",
+                };
+                let result = function.value(format!("icmp {} i64 {}, {}", cond, left, right));
+
+                // Booleans are kept as `i64`s like every other value.
+                function.value(format!("zext i1 {} to i64", result))
+            }
+            Ast::Neg { expr, .. } => {
+                let expr = gen_ast(expr, function, env, mode)?;
+                let result = gen_arith(function, "sub", "0", &expr, mode);
+
+                gen_mask(function, result, mode)
+            }
+            Ast::Not { expr, .. } => {
+                let expr = gen_ast(expr, function, env, mode)?;
+                let result = function.value(format!("xor i64 {}, -1", expr));
+
+                gen_mask(function, result, mode)
+            }
+            Ast::Group { expr, .. } => gen_ast(expr, function, env, mode)?,
+            // Values are immutable, so a binding is just another name for its value.
+            Ast::Let {
+                name, value, body, ..
+            } => {
+                let value = gen_ast(value, function, env, mode)?;
+
+                env.push((name.as_str(), value));
+
+                let body = gen_ast(body, function, env, mode);
+
+                env.pop();
+                body?
+            }
+            Ast::If {
+                cond, then, else_, ..
+            } => {
+                let cond = gen_ast(cond, function, env, mode)?;
+                let cond = function.value(format!("icmp ne i64 {}, 0", cond));
+                let then_block = function.new_block();
+                let else_block = function.new_block();
+                let end_block = function.new_block();
+
+                function.inst(format!(
+                    "br i1 {}, label %{}, label %{}",
+                    cond, then_block, else_block
+                ));
+
+                // The branches may have split their blocks, so the `phi` refers to the blocks
+                // they end in.
+                function.label(then_block);
+
+                let then = gen_ast(then, function, env, mode)?;
+                let then_end = function.block.clone();
+
+                function.inst(format!("br label %{}", end_block));
+                function.label(else_block);
+
+                let else_ = gen_ast(else_, function, env, mode)?;
+                let else_end = function.block.clone();
+
+                function.inst(format!("br label %{}", end_block));
+                function.label(end_block);
+                function.value(format!(
+                    "phi i64 [ {}, %{} ], [ {}, %{} ]",
+                    then, then_end, else_, else_end
+                ))
+            }
+            // Analysis has already rejected unknown variables.
+            Ast::Var { name, .. } => env
+                .iter()
+                .rev()
+                .find(|(var, _)| *var == name.as_str())
+                .unwrap()
+                .1
+                .clone(),
+        })
+    })
+}
@@ -0,0 +1,781 @@
+mod aarch64;
+pub(crate) mod fold;
+mod llvm;
+mod wasm;
+mod x86_64;
+
+use crate::ast::*;
+use crate::constants::Constants;
+use crate::run::IntMode;
+use diagnostics::{Diagnostic, Severity, Span, Spanned};
+use faerie::{ArtifactBuilder, Decl};
+use std::fmt::Write;
+use std::ops::Range;
+use target_lexicon::{Architecture, OperatingSystem, Triple};
+
+/// Maps ranges of generated machine code back to the node they were generated for.
+type SourceMap = Vec<(Range<usize>, Span)>;
+
+/// The values on the stack while generating code, bottom first, named if they are bound
+/// by a `let`.
+type Stack<'a> = Vec<Option<&'a str>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    /// An object file exporting `_start`.
+    Obj,
+    /// Textual assembly, in the GNU assembler's syntax.
+    Asm,
+    /// A linked executable.
+    Bin,
+    /// Textual LLVM IR, which doesn't depend on the target.
+    LlvmIr,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options<'a> {
+    pub target: Triple,
+    /// Where to write a listing of the generated code.
+    pub listing: Option<&'a str>,
+    pub emit: Emit,
+    /// The linker to invoke, followed by any arguments to pass before the object file.
+    pub linker: &'a str,
+    /// Keep the object file around after linking.
+    pub keep_obj: bool,
+    pub int_mode: IntMode,
+}
+
+/// Failures detected by generated code. Each node that can fail has a routine placed after
+/// the rest of the program, writing a message to stderr and exiting with status 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Trap {
+    DivisionByZero,
+    Overflow,
+}
+
+impl Trap {
+    fn label(self) -> &'static str {
+        match self {
+            Self::DivisionByZero => "div_by_zero",
+            Self::Overflow => "overflow",
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            Self::DivisionByZero => "division by zero\n",
+            Self::Overflow => "integer overflow\n",
+        }
+    }
+}
+
+/// The routine for a trap in the code generated for one node, along with the branches to it.
+struct TrapRoutine {
+    label: String,
+    /// The trap's message, followed by the source of the node.
+    message: String,
+    branches: Vec<usize>,
+}
+
+/// The longest source snippet put in a trap message, in characters.
+const MAX_SNIPPET: usize = 60;
+
+/// The message for `trap` in the code generated for `span`, pointing at its source. Only
+/// the first line of the source is shown, so the message stays short.
+fn trap_message(trap: Trap, span: Option<Span>) -> String {
+    let span = match span {
+        Some(span) => span,
+        None => return trap.message().to_string(),
+    };
+    let snippet = span
+        .file
+        .source
+        .get(span.start.offset..span.end.offset)
+        .and_then(|source| source.lines().next());
+
+    match snippet {
+        Some(snippet) if snippet.chars().count() > MAX_SNIPPET => format!(
+            "{}  at {}...\n",
+            trap.message(),
+            snippet.chars().take(MAX_SNIPPET).collect::<String>()
+        ),
+        Some(snippet) => format!("{}  at {}\n", trap.message(), snippet),
+        None => trap.message().to_string(),
+    }
+}
+
+/// Machine code under construction. Every instruction keeps the assembly it was encoded
+/// from, so a single walk over the tree produces both the object code and `--emit asm`.
+#[derive(Default)]
+struct Code {
+    bytes: Vec<u8>,
+    asm: Vec<(Range<usize>, String)>,
+    map: SourceMap,
+    /// Offsets of the branches to trap routines, which are patched once the routines are
+    /// placed after the rest of the program.
+    traps: Vec<(usize, Trap)>,
+    /// The number of conditionals generated so far, which number their labels.
+    conditionals: usize,
+    /// The number of locals the WebAssembly backend uses.
+    locals: u32,
+}
+
+impl Code {
+    fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn inst(&mut self, bytes: &[u8], asm: impl Into<String>) {
+        let start = self.len();
+
+        self.bytes.extend(bytes);
+        self.asm.push((start..self.len(), asm.into()));
+    }
+
+    fn label(&mut self, name: &str) {
+        self.asm
+            .push((self.len()..self.len(), format!("{}:", name)));
+    }
+
+    /// Returns the labels of the `else` branch and the end of a new conditional.
+    fn conditional_labels(&mut self) -> (String, String) {
+        let n = self.conditionals;
+
+        self.conditionals += 1;
+        (format!("else_{}", n), format!("end_{}", n))
+    }
+
+    /// Records that everything emitted since `start` was generated for `span`.
+    fn mark(&mut self, start: usize, span: Span) {
+        self.map.push((start..self.len(), span));
+    }
+
+    /// The span of the innermost node the code at `offset` was generated for.
+    fn span_at(&self, offset: usize) -> Option<Span> {
+        self.map
+            .iter()
+            .filter(|(range, _)| range.contains(&offset))
+            .min_by_key(|(range, _)| range.len())
+            .map(|(_, span)| *span)
+    }
+
+    /// Groups the branches to trap routines by the trap and the node they were generated
+    /// for, in the order they were emitted, and points the assembly of every branch at the
+    /// label of its routine. The branches themselves are patched by the backend once it
+    /// places the routines.
+    fn trap_routines(&mut self) -> Vec<TrapRoutine> {
+        let mut routines = Vec::<(Trap, Option<Span>, TrapRoutine)>::new();
+
+        for (at, trap) in std::mem::take(&mut self.traps) {
+            let span = self.span_at(at);
+            let i = match routines
+                .iter()
+                .position(|(t, s, _)| *t == trap && *s == span)
+            {
+                Some(i) => i,
+                None => {
+                    routines.push((
+                        trap,
+                        span,
+                        TrapRoutine {
+                            label: format!("{}_{}", trap.label(), routines.len()),
+                            message: trap_message(trap, span),
+                            branches: Vec::new(),
+                        },
+                    ));
+                    routines.len() - 1
+                }
+            };
+            let routine = &mut routines[i].2;
+
+            routine.branches.push(at);
+
+            // The branch was emitted with the trap's label as its last operand.
+            if let Some((_, asm)) = self
+                .asm
+                .iter_mut()
+                .find(|(range, _)| range.start == at && !range.is_empty())
+            {
+                asm.truncate(asm.rfind(' ').map_or(0, |i| i + 1));
+                asm.push_str(&routine.label);
+            }
+        }
+
+        routines
+            .into_iter()
+            .map(|(_, _, routine)| routine)
+            .collect()
+    }
+}
+
+/// Compiles `program` to `output`. `constants` holds its constant subexpressions, as
+/// found by [`Constants::find`].
+pub fn compile(
+    program: &[Ast],
+    constants: &Constants,
+    output: &str,
+    options: &Options,
+) -> Result<(), Diagnostic> {
+    if options.emit == Emit::LlvmIr {
+        return write_file(output, emit_llvm_ir(program, constants, options.int_mode)?);
+    }
+
+    let target = &options.target;
+    let code = gen_program(program, constants, target, options.int_mode)?;
+
+    if let Some(listing) = options.listing {
+        write_file(listing, gen_listing(program, &code))?;
+    }
+
+    match options.emit {
+        Emit::Asm => write_file(output, gen_asm(&code, target))?,
+        // A module is loaded as is, so there is nothing to link.
+        Emit::Obj | Emit::Bin if target.architecture == Architecture::Wasm32 => {
+            write_file(output, wasm::gen_module(&code))?
+        }
+        Emit::Obj => write_object(output, target, code.bytes)?,
+        Emit::Bin => {
+            let obj_filename = format!("{}.o", output);
+
+            write_object(&obj_filename, target, code.bytes)?;
+
+            let linked = link(options.linker, &obj_filename, output);
+
+            if !options.keep_obj {
+                // A leftover object file is harmless, so failing to remove it isn't an error.
+                let _ = std::fs::remove_file(&obj_filename);
+            }
+
+            linked?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `linker` to turn the object file `obj` into the executable `output`. A partially
+/// written executable is removed if linking fails.
+fn link(linker: &str, obj: &str, output: &str) -> Result<(), Diagnostic> {
+    let program = linker.split_whitespace().next().unwrap_or("ld");
+    let result = link_command(linker, obj, output).output();
+
+    if !matches!(&result, Ok(result) if result.status.success()) {
+        let _ = std::fs::remove_file(output);
+    }
+
+    let msg = match result {
+        Ok(result) if result.status.success() => return Ok(()),
+        Ok(result) => format!(
+            "Linking with `{}` failed: {}\n{}",
+            program,
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim_end(),
+        ),
+        Err(e) => format!("Could not run the linker `{}`: {}", program, e),
+    };
+
+    Err(Diagnostic::new(Severity::Error, None, msg))
+}
+
+/// The command linking `obj` into `output` with `linker`. The program defines `_start`
+/// itself and makes system calls directly, so it is linked statically without the C
+/// runtime. That keeps toolchains defaulting to position-independent executables from
+/// warning about or rejecting the absolute addresses in it, and works for `ld` as well as
+/// compiler drivers like `cc`.
+fn link_command(linker: &str, obj: &str, output: &str) -> std::process::Command {
+    let mut args = linker.split_whitespace();
+    let mut command = std::process::Command::new(args.next().unwrap_or("ld"));
+
+    command
+        .arg("-static")
+        .arg("-nostdlib")
+        .args(args)
+        .arg(obj)
+        .arg("-o")
+        .arg(output);
+    command
+}
+
+/// Generates the assembly for `program` for `target`.
+pub fn emit_asm(
+    program: &[Ast],
+    constants: &Constants,
+    target: &Triple,
+    mode: IntMode,
+) -> Result<String, Diagnostic> {
+    gen_program(program, constants, target, mode).map(|code| gen_asm(&code, target))
+}
+
+/// Generates LLVM IR defining `main`, which returns the result of the last expression in
+/// `program`. Constant subexpressions are folded first, as they are for machine code.
+pub fn emit_llvm_ir(
+    program: &[Ast],
+    constants: &Constants,
+    mode: IntMode,
+) -> Result<String, Diagnostic> {
+    let program = program
+        .iter()
+        .map(|ast| fold::fold(ast, constants))
+        .collect::<Vec<_>>();
+
+    llvm::gen_program(&program, mode)
+}
+
+/// Generates a program printing the result of every expression in `program` and exiting
+/// with the last one, or for WebAssembly a function returning the last one.
+/// The native backends exit through Linux system calls, so other operating systems are
+/// rejected along with other architectures.
+fn gen_program(
+    program: &[Ast],
+    constants: &Constants,
+    target: &Triple,
+    mode: IntMode,
+) -> Result<Code, Diagnostic> {
+    let program = program
+        .iter()
+        .map(|ast| fold::fold(ast, constants))
+        .collect::<Vec<_>>();
+    let mut code = Code::default();
+
+    match (&target.architecture, &target.operating_system) {
+        (Architecture::X86_64, OperatingSystem::Linux) => {
+            x86_64::gen_program(&program, &mut code, mode)?
+        }
+        (Architecture::Aarch64(_), OperatingSystem::Linux) => {
+            aarch64::gen_program(&program, &mut code, mode)?
+        }
+        (Architecture::Wasm32, _) => wasm::gen_program(&program, &mut code, mode)?,
+        _ => {
+            return Err(Diagnostic::new(
+                Severity::Error,
+                None,
+                format!("Code generation for `{}` is not supported", target),
+            ))
+        }
+    }
+
+    Ok(code)
+}
+
+fn write_error(filename: &str, e: impl std::fmt::Display) -> Diagnostic {
+    Diagnostic::new(
+        Severity::Error,
+        None,
+        format!("Cannot write `{}`: {}", filename, e),
+    )
+}
+
+fn write_file(filename: &str, contents: impl AsRef<[u8]>) -> Result<(), Diagnostic> {
+    std::fs::write(filename, contents).map_err(|e| write_error(filename, e))
+}
+
+fn write_object(filename: &str, target: &Triple, code: Vec<u8>) -> Result<(), Diagnostic> {
+    let obj_file = std::fs::File::create(filename).map_err(|e| write_error(filename, e))?;
+    let mut obj = ArtifactBuilder::new(target.clone())
+        .name(filename.into())
+        .finish();
+
+    // `_start` is the only symbol, so neither can fail.
+    obj.declare("_start", Decl::function()).unwrap();
+    obj.define("_start", code).unwrap();
+    obj.write(obj_file).map_err(|e| write_error(filename, e))
+}
+
+fn gen_asm(code: &Code, target: &Triple) -> String {
+    if let Architecture::Wasm32 = target.architecture {
+        return wasm::gen_wat(code);
+    }
+
+    let mut out = String::new();
+
+    if let Architecture::X86_64 = target.architecture {
+        out.push_str(".intel_syntax noprefix\n");
+    }
+
+    out.push_str(".globl _start\n_start:\n");
+
+    for (_, asm) in &code.asm {
+        if asm.ends_with(':') {
+            writeln!(out, "{}", asm).unwrap();
+        } else {
+            writeln!(out, "    {}", asm).unwrap();
+        }
+    }
+
+    out
+}
+
+/// Returns how many values above the binding of `name` are on the stack. Analysis has
+/// already rejected unknown variables.
+fn lookup(stack: &Stack, name: &str) -> usize {
+    let slot = stack.iter().rposition(|var| *var == Some(name)).unwrap();
+
+    stack.len() - 1 - slot
+}
+
+fn unsupported_call(span: Span) -> Diagnostic {
+    Diagnostic::new(
+        Severity::Error,
+        None,
+        "Function calls are not supported by the code generator",
+    )
+    .label(Severity::Error, span, None::<String>)
+}
+
+fn unsupported_float(span: Span) -> Diagnostic {
+    Diagnostic::new(
+        Severity::Error,
+        None,
+        "Floating point numbers are not supported by the code generator",
+    )
+    .label(Severity::Error, span, None::<String>)
+}
+
+fn unsupported_string(span: Span) -> Diagnostic {
+    Diagnostic::new(
+        Severity::Error,
+        None,
+        "Strings are not supported by the code generator",
+    )
+    .label(Severity::Error, span, None::<String>)
+}
+
+/// Produces an assembler-style listing, with every source line followed by the
+/// instructions generated for the expressions starting on that line.
+fn gen_listing(program: &[Ast], code: &Code) -> String {
+    let mut out = String::new();
+
+    if let Some(ast) = program.first() {
+        for (i, line) in ast.span().file.source.lines().enumerate() {
+            writeln!(out, "{:>5} | {}", i + 1, line).unwrap();
+
+            for (range, _) in code.map.iter().filter(|(_, span)| span.start.line == i) {
+                write_insts(&mut out, code, range.clone());
+            }
+        }
+    }
+
+    let end = code
+        .map
+        .iter()
+        .map(|(range, _)| range.end)
+        .max()
+        .unwrap_or(0);
+
+    if end < code.len() {
+        writeln!(out, "{:>5} | <epilogue>", "").unwrap();
+        write_insts(&mut out, code, end..code.len());
+    }
+
+    out
+}
+
+fn write_insts(out: &mut String, code: &Code, range: Range<usize>) {
+    for (inst, asm) in &code.asm {
+        if !range.contains(&inst.start) {
+            continue;
+        }
+
+        let bytes = code.bytes[inst.clone()]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        writeln!(
+            out,
+            "{:>5} |     {:04x}: {:<30} {}",
+            "", inst.start, bytes, asm
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64", target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::analysis;
+    use crate::run::Overflow;
+    use diagnostics::FileInterner;
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn analysis_options(mode: IntMode) -> analysis::Options {
+        analysis::Options {
+            redundant_parens: false,
+            int_mode: mode,
+        }
+    }
+
+    /// A path to build an executable at. Tests run in parallel, so every build gets one of
+    /// its own.
+    fn executable() -> String {
+        static BUILDS: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "math-lang-test-{}-{}",
+            std::process::id(),
+            BUILDS.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        path.to_str().unwrap().to_string()
+    }
+
+    /// Runs the executable at `path` and removes it.
+    fn run_executable(path: &str) -> std::process::Output {
+        let built = Command::new(path).output().unwrap();
+
+        std::fs::remove_file(path).unwrap();
+        built
+    }
+
+    /// Builds `source` for the host and runs it.
+    fn build_and_run(source: &str, mode: IntMode) -> std::process::Output {
+        let codegen_options = Options {
+            target: target_lexicon::HOST,
+            listing: None,
+            emit: Emit::Bin,
+            linker: "ld",
+            keep_obj: false,
+            int_mode: mode,
+        };
+        let output = executable();
+
+        if crate::compile(
+            &FileInterner::new(),
+            "<test>",
+            source,
+            &output,
+            &codegen_options,
+            analysis_options(mode),
+        )
+        .is_err()
+        {
+            panic!("failed to compile `{}`", source);
+        }
+
+        run_executable(&output)
+    }
+
+    /// Checks that `source`, built for the host and executed, prints what `run` evaluates it
+    /// to, or fails with an overflow as `run` does.
+    fn assert_build_matches_run(source: &str, mode: IntMode) {
+        let built = build_and_run(source, mode);
+
+        match crate::run(
+            &FileInterner::new(),
+            "<test>",
+            source,
+            analysis_options(mode),
+        ) {
+            Ok(values) => {
+                let expected = values
+                    .value
+                    .iter()
+                    .map(|(_, value)| format!("{}\n", value))
+                    .collect::<String>();
+
+                assert_eq!(
+                    String::from_utf8_lossy(&built.stdout),
+                    expected,
+                    "{}",
+                    source
+                );
+            }
+            // Every source that fails is a single operation, so its trap points at all of it.
+            Err(_) => {
+                assert_eq!(built.status.code(), Some(1), "{}", source);
+                assert_eq!(
+                    String::from_utf8_lossy(&built.stderr),
+                    format!("integer overflow\n  at {}\n", source),
+                    "{}",
+                    source
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn exit_status_is_the_low_byte_of_the_last_result() {
+        for &(source, status) in &[("2 + 3", 5), ("7; 255", 255), ("1; 300", 44), ("256", 0)] {
+            let built = build_and_run(source, IntMode::default());
+
+            assert_eq!(built.status.code(), Some(status), "{}", source);
+        }
+    }
+
+    /// Hosts other than AArch64 can't run its code, so the addition is checked against the
+    /// bytes an assembler produces for it.
+    #[test]
+    fn aarch64_add() {
+        let target = "aarch64-unknown-linux-gnu".parse::<Triple>().unwrap();
+        let program = crate::parse_test("2 + 3").expect("syntax error");
+        let mode = IntMode {
+            width: 64,
+            overflow: Overflow::Wrap,
+        };
+        // Without constants the addition isn't folded.
+        let code = match gen_program(&program, &Constants::new(), &target, mode) {
+            Ok(code) => code,
+            Err(_) => panic!("failed to generate code for `2 + 3`"),
+        };
+        let (range, _) = code
+            .map
+            .iter()
+            .find(|(_, span)| *span == program[0].span())
+            .unwrap();
+
+        assert_eq!(
+            code.bytes[range.clone()],
+            [
+                0xe1, 0x07, 0x41, 0xf8, // ldr x1, [sp], #16
+                0xe0, 0x07, 0x41, 0xf8, // ldr x0, [sp], #16
+                0x00, 0x00, 0x01, 0x8b, // add x0, x0, x1
+                0xe0, 0x0f, 0x1f, 0xf8, // str x0, [sp, #-16]!
+            ]
+        );
+
+        let asm = gen_asm(&code, &target);
+
+        assert!(asm.contains("    movz x0, #0x2\n"), "{}", asm);
+        assert!(asm.contains("    movz x0, #0x3\n"), "{}", asm);
+        assert!(asm.contains("    add x0, x0, x1\n"), "{}", asm);
+        assert!(asm.contains("    svc #0\n"), "{}", asm);
+    }
+
+    #[test]
+    fn pow_prints_its_result() {
+        // The conditionals keep the bases from being folded, so the loop computes them.
+        let source = "2 ** 10; (if 1 < 2 then 2 else 0) ** 10; (if 1 < 2 then 7 else 0) ** 0";
+        let built = build_and_run(source, IntMode::default());
+
+        assert_eq!(String::from_utf8_lossy(&built.stdout), "1024\n1024\n1\n");
+        assert_build_matches_run(source, IntMode::default());
+    }
+
+    #[test]
+    fn division_is_unsigned() {
+        // Signed division would take the dividend for -1. The conditional keeps it from
+        // being folded.
+        let max = "(if 1 < 2 then 18446744073709551615 else 0)";
+        let source = &format!("{0} / 3; {0} / 9223372036854775808; {0} % 10", max);
+        let built = build_and_run(source, IntMode::default());
+
+        assert_eq!(
+            String::from_utf8_lossy(&built.stdout),
+            "6148914691236517205\n1\n5\n"
+        );
+        assert_build_matches_run(source, IntMode::default());
+    }
+
+    #[test]
+    fn empty_program_exits_with_0() {
+        let mode = IntMode::default();
+        let x86_64 = "x86_64-unknown-linux-gnu".parse::<Triple>().unwrap();
+
+        match emit_asm(&[], &Constants::new(), &x86_64, mode) {
+            Ok(asm) => assert!(asm.contains("    push 0\n    pop rdi\n"), "{}", asm),
+            Err(_) => panic!("failed to generate code for an empty program"),
+        }
+
+        // Parsing rejects an empty program, so its code is generated and linked directly.
+        let code = match gen_program(&[], &Constants::new(), &target_lexicon::HOST, mode) {
+            Ok(code) => code,
+            Err(_) => panic!("failed to generate code for an empty program"),
+        };
+        let output = executable();
+        let obj = format!("{}.o", output);
+
+        assert!(write_object(&obj, &target_lexicon::HOST, code.bytes).is_ok());
+
+        let linked = link("ld", &obj, &output);
+
+        std::fs::remove_file(&obj).unwrap();
+        assert!(linked.is_ok());
+
+        let built = run_executable(&output);
+
+        assert_eq!(built.status.code(), Some(0));
+        assert!(built.stdout.is_empty());
+    }
+
+    #[test]
+    fn unwritable_output_is_an_error() {
+        let program = crate::parse_test("1").expect("syntax error");
+        let output = std::env::temp_dir()
+            .join("math-lang-test-missing")
+            .join("out");
+
+        for &emit in &[Emit::Asm, Emit::Obj, Emit::LlvmIr] {
+            let options = Options {
+                target: "x86_64-unknown-linux-gnu".parse().unwrap(),
+                listing: None,
+                emit,
+                linker: "ld",
+                keep_obj: false,
+                int_mode: IntMode::default(),
+            };
+
+            assert!(compile(
+                &program,
+                &Constants::new(),
+                output.to_str().unwrap(),
+                &options
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn pow_overflow_matches_run() {
+        // The conditionals keep the bases from being folded.
+        let sources = [
+            "2 ** 64",
+            "(if 1 < 2 then 2 else 0) ** 63",
+            "(if 1 < 2 then 2 else 0) ** 64",
+            "(if 1 < 2 then 2 else 0) ** 32",
+            "(if 1 < 2 then 3 else 0) ** 41",
+        ];
+
+        for &overflow in &[Overflow::Trap, Overflow::Wrap] {
+            for &width in &[32, 64] {
+                for source in &sources {
+                    assert_build_matches_run(source, IntMode { width, overflow });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_statement_is_printed() {
+        let source = "1; 2 + 3; 4";
+
+        assert_build_matches_run(source, IntMode::default());
+        assert_eq!(
+            String::from_utf8_lossy(&build_and_run(source, IntMode::default()).stdout),
+            "1\n5\n4\n"
+        );
+    }
+
+    #[test]
+    fn traps_show_their_source() {
+        let source = "1;\n6 / (if 1 < 2 then 0 else 1);\n2";
+        let built = build_and_run(source, IntMode::default());
+
+        assert_eq!(built.status.code(), Some(1));
+        assert_eq!(String::from_utf8_lossy(&built.stdout), "1\n");
+        assert_eq!(
+            String::from_utf8_lossy(&built.stderr),
+            "division by zero\n  at 6 / (if 1 < 2 then 0 else 1)\n"
+        );
+
+        // Only the first line of a node spanning several is shown.
+        let source = "let x = if 1 < 2 then 0 else 1 in 7 %\n    x";
+        let built = build_and_run(source, IntMode::default());
+
+        assert_eq!(
+            String::from_utf8_lossy(&built.stderr),
+            "division by zero\n  at 7 %\n"
+        );
+    }
+}
@@ -0,0 +1,457 @@
+//! Emits a WebAssembly module exporting a single function, `main`, which takes no
+//! arguments and returns the result of the last expression as an `i64`.
+//!
+//! WebAssembly is a stack machine as well, but has no registers or stack memory to spill
+//! to. Variables and intermediate values are kept in locals instead, numbered by their
+//! position in the stack of bindings. Traps abort execution in the host, division by zero
+//! traps by itself and overflow through `unreachable`.
+
+use super::{unsupported_call, unsupported_float, unsupported_string, Code, Stack};
+use crate::ast::*;
+use crate::run::{IntMode, Overflow};
+use diagnostics::{Diagnostic, Spanned};
+use std::fmt::Write;
+
+pub fn gen_program(program: &[Ast], code: &mut Code, mode: IntMode) -> Result<(), Diagnostic> {
+    for (i, ast) in program.iter().enumerate() {
+        gen_ast(ast, code, &mut Vec::new(), mode)?;
+
+        // Only the last result is returned.
+        if i + 1 < program.len() {
+            let start = code.len();
+
+            code.inst(&[0x1a], "drop");
+            code.mark(start, ast.span());
+        }
+    }
+
+    if program.is_empty() {
+        i64_const(code, 0);
+    }
+
+    Ok(())
+}
+
+/// Wraps the body of `main` in a module.
+pub fn gen_module(code: &Code) -> Vec<u8> {
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+
+    // A single type, taking nothing and returning an `i64`.
+    section(&mut module, 1, &[0x01, 0x60, 0x00, 0x01, 0x7e]);
+    // A single function of that type.
+    section(&mut module, 3, &[0x01, 0x00]);
+
+    let mut export = vec![0x01, 0x04];
+
+    export.extend(b"main");
+    export.extend(&[0x00, 0x00]);
+    section(&mut module, 7, &export);
+
+    let mut body = Vec::new();
+
+    if code.locals > 0 {
+        body.push(0x01);
+        uleb(&mut body, code.locals as u64);
+        body.push(0x7e);
+    } else {
+        body.push(0x00);
+    }
+
+    body.extend(&code.bytes);
+    body.push(0x0b);
+
+    let mut bodies = vec![0x01];
+
+    uleb(&mut bodies, body.len() as u64);
+    bodies.extend(body);
+    section(&mut module, 10, &bodies);
+
+    module
+}
+
+/// Produces the module in the text format.
+pub fn gen_wat(code: &Code) -> String {
+    let mut out = String::new();
+
+    out.push_str("(module\n  (func (export \"main\") (result i64)\n");
+
+    if code.locals > 0 {
+        writeln!(out, "    (local{})", " i64".repeat(code.locals as usize)).unwrap();
+    }
+
+    for (_, asm) in &code.asm {
+        writeln!(out, "    {}", asm).unwrap();
+    }
+
+    out.push_str("  )\n)\n");
+    out
+}
+
+fn section(module: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    module.push(id);
+    uleb(module, contents.len() as u64);
+    module.extend(contents);
+}
+
+fn uleb(out: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+
+        val >>= 7;
+
+        if val == 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+/// Done once the remaining bits are all copies of the sign bit of the last byte.
+fn sleb(out: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+
+        val >>= 7;
+
+        if val == 0 && byte & 0x40 == 0 || val == -1 && byte & 0x40 != 0 {
+            out.push(byte);
+            return;
+        }
+
+        out.push(byte | 0x80);
+    }
+}
+
+/// Constants are signed, integers above `i64::MAX` are written as their two's complement.
+fn i64_const(code: &mut Code, val: u64) {
+    let mut bytes = vec![0x42];
+
+    sleb(&mut bytes, val as i64);
+    code.inst(&bytes, format!("i64.const {}", val as i64));
+}
+
+fn local_inst(code: &mut Code, opcode: u8, name: &str, local: u32) {
+    let mut bytes = vec![opcode];
+
+    uleb(&mut bytes, local as u64);
+    code.inst(&bytes, format!("{} {}", name, local));
+}
+
+fn local_get(code: &mut Code, local: u32) {
+    local_inst(code, 0x20, "local.get", local);
+}
+
+fn local_set(code: &mut Code, local: u32) {
+    local_inst(code, 0x21, "local.set", local);
+}
+
+fn local_tee(code: &mut Code, local: u32) {
+    local_inst(code, 0x22, "local.tee", local);
+}
+
+/// Pushes a binding, or a temporary if `name` is `None`, and returns its local.
+fn alloc<'a>(code: &mut Code, stack: &mut Stack<'a>, name: Option<&'a str>) -> u32 {
+    stack.push(name);
+    code.locals = code.locals.max(stack.len() as u32);
+
+    stack.len() as u32 - 1
+}
+
+/// Analysis has already rejected unknown variables.
+fn lookup_local(stack: &Stack, name: &str) -> u32 {
+    stack.iter().rposition(|var| *var == Some(name)).unwrap() as u32
+}
+
+/// Traps if the `i32` on top of the stack is not 0.
+fn gen_trap_if(code: &mut Code) {
+    code.inst(&[0x04, 0x40], "if");
+    code.inst(&[0x00], "unreachable");
+    code.inst(&[0x0b], "end");
+}
+
+/// Truncates the value on top of the stack to the integer width.
+fn gen_mask(code: &mut Code, mode: IntMode) {
+    if mode.width < 64 {
+        i64_const(code, mode.mask(u64::max_value()));
+        code.inst(&[0x83], "i64.and");
+    }
+}
+
+/// Traps if the result on top of the stack doesn't fit in a narrower width when trapping.
+/// Results are computed in 64 bits, so they fit if their upper bits are clear.
+fn gen_check_overflow(code: &mut Code, stack: &mut Stack, mode: IntMode) {
+    if mode.overflow == Overflow::Wrap || mode.width >= 64 {
+        return;
+    }
+
+    let result = alloc(code, stack, None);
+
+    local_tee(code, result);
+    i64_const(code, mode.width as u64);
+    code.inst(&[0x88], "i64.shr_u");
+    i64_const(code, 0);
+    code.inst(&[0x52], "i64.ne");
+    gen_trap_if(code);
+    local_get(code, result);
+    stack.pop();
+}
+
+/// Applies `op` to the two operands on top of the stack, trapping on 64-bit overflow. There
+/// are no flags, so the result is compared with the operands instead.
+fn gen_checked_op(code: &mut Code, stack: &mut Stack, op: Op) {
+    let left = alloc(code, stack, None);
+    let right = alloc(code, stack, None);
+    let result = alloc(code, stack, None);
+
+    local_set(code, right);
+    local_set(code, left);
+
+    match op {
+        // A sum that wrapped around is less than either operand.
+        Op::Add => {
+            local_get(code, left);
+            local_get(code, right);
+            code.inst(&[0x7c], "i64.add");
+            local_tee(code, result);
+            local_get(code, left);
+            code.inst(&[0x54], "i64.lt_u");
+            gen_trap_if(code);
+        }
+        Op::Sub => {
+            local_get(code, left);
+            local_get(code, right);
+            code.inst(&[0x54], "i64.lt_u");
+            gen_trap_if(code);
+            local_get(code, left);
+            local_get(code, right);
+            code.inst(&[0x7d], "i64.sub");
+            local_set(code, result);
+        }
+        // A product that wrapped around no longer gives back the left operand when divided
+        // by the right one.
+        _ => {
+            local_get(code, left);
+            local_get(code, right);
+            code.inst(&[0x7e], "i64.mul");
+            local_set(code, result);
+            local_get(code, right);
+            code.inst(&[0x50], "i64.eqz");
+            code.inst(&[0x45], "i32.eqz");
+            code.inst(&[0x04, 0x40], "if");
+            local_get(code, result);
+            local_get(code, right);
+            code.inst(&[0x80], "i64.div_u");
+            local_get(code, left);
+            code.inst(&[0x52], "i64.ne");
+            gen_trap_if(code);
+            code.inst(&[0x0b], "end");
+        }
+    }
+
+    local_get(code, result);
+    stack.truncate(stack.len() - 3);
+}
+
+/// Exponentiation by squaring, as in the other backends. Overflow isn't detected here
+/// either.
+fn gen_pow(code: &mut Code, stack: &mut Stack) {
+    let base = alloc(code, stack, None);
+    let exponent = alloc(code, stack, None);
+    let result = alloc(code, stack, None);
+
+    local_set(code, exponent);
+    local_set(code, base);
+    i64_const(code, 1);
+    local_set(code, result);
+    code.inst(&[0x02, 0x40], "block");
+    code.inst(&[0x03, 0x40], "loop");
+    local_get(code, exponent);
+    code.inst(&[0x50], "i64.eqz");
+    code.inst(&[0x0d, 0x01], "br_if 1");
+    local_get(code, exponent);
+    i64_const(code, 1);
+    code.inst(&[0x83], "i64.and");
+    code.inst(&[0xa7], "i32.wrap_i64");
+    code.inst(&[0x04, 0x40], "if");
+    local_get(code, result);
+    local_get(code, base);
+    code.inst(&[0x7e], "i64.mul");
+    local_set(code, result);
+    code.inst(&[0x0b], "end");
+    local_get(code, base);
+    local_get(code, base);
+    code.inst(&[0x7e], "i64.mul");
+    local_set(code, base);
+    local_get(code, exponent);
+    i64_const(code, 1);
+    code.inst(&[0x88], "i64.shr_u");
+    local_set(code, exponent);
+    code.inst(&[0x0c, 0x00], "br 0");
+    code.inst(&[0x0b], "end");
+    code.inst(&[0x0b], "end");
+    local_get(code, result);
+    stack.truncate(stack.len() - 3);
+}
+
+fn gen_ast<'a>(
+    ast: &'a Ast,
+    code: &mut Code,
+    stack: &mut Stack<'a>,
+    mode: IntMode,
+) -> Result<(), Diagnostic> {
+    crate::grow_stack(|| {
+        match ast {
+            Ast::Int { span, val } => {
+                let start = code.len();
+
+                i64_const(code, *val);
+                code.mark(start, *span);
+            }
+            Ast::Float { span, .. } => return Err(unsupported_float(*span)),
+            Ast::Str { span, .. } => return Err(unsupported_string(*span)),
+            Ast::Call { span, .. } => return Err(unsupported_call(*span)),
+            Ast::Error { .. } => unreachable!("analysis rejects programs with syntax errors"),
+            Ast::Op {
+                span,
+                op,
+                left,
+                right,
+                ..
+            } => {
+                gen_ast(left, code, stack, mode)?;
+                gen_ast(right, code, stack, mode)?;
+
+                let start = code.len();
+                let checks_64 = mode.overflow == Overflow::Trap && mode.width >= 64;
+
+                match op {
+                    Op::Add | Op::Sub | Op::Mul if checks_64 => gen_checked_op(code, stack, *op),
+                    Op::Add => code.inst(&[0x7c], "i64.add"),
+                    Op::Sub => code.inst(&[0x7d], "i64.sub"),
+                    Op::Mul => code.inst(&[0x7e], "i64.mul"),
+                    Op::Div => code.inst(&[0x80], "i64.div_u"),
+                    Op::Mod => code.inst(&[0x82], "i64.rem_u"),
+                    Op::Pow => gen_pow(code, stack),
+                    Op::BitAnd => code.inst(&[0x83], "i64.and"),
+                    Op::BitOr => code.inst(&[0x84], "i64.or"),
+                    Op::BitXor => code.inst(&[0x85], "i64.xor"),
+                }
+
+                if let Op::Add | Op::Sub | Op::Mul = op {
+                    gen_check_overflow(code, stack, mode);
+                }
+
+                if let Op::Add | Op::Sub | Op::Mul | Op::Pow = op {
+                    gen_mask(code, mode);
+                }
+
+                code.mark(start, *span);
+            }
+            Ast::Cmp {
+                span,
+                op,
+                left,
+                right,
+            } => {
+                gen_ast(left, code, stack, mode)?;
+                gen_ast(right, code, stack, mode)?;
+
+                let start = code.len();
+
+                // Values are unsigned, so the unsigned comparisons are used.
+                match op {
+                    CmpOp::Lt => code.inst(&[0x54], "i64.lt_u"),
+                    CmpOp::Gt => code.inst(&[0x56], "i64.gt_u"),
+                    CmpOp::Le => code.inst(&[0x58], "i64.le_u"),
+                    CmpOp::Ge => code.inst(&[0x5a], "i64.ge_u"),
+                    CmpOp::Eq => code.inst(&[0x51], "i64.eq"),
+                    CmpOp::Ne => code.inst(&[0x52], "i64.ne"),
+                }
+
+                // Comparisons produce an `i32`, every value is kept as an `i64`.
+                code.inst(&[0xad], "i64.extend_i32_u");
+                code.mark(start, *span);
+            }
+            // Negation is `0 - expr`, so it traps like a subtraction unless it wraps.
+            Ast::Neg { span, expr } => {
+                i64_const(code, 0);
+                gen_ast(expr, code, stack, mode)?;
+
+                let start = code.len();
+
+                if mode.overflow == Overflow::Trap && mode.width >= 64 {
+                    gen_checked_op(code, stack, Op::Sub);
+                } else {
+                    code.inst(&[0x7d], "i64.sub");
+                }
+
+                gen_check_overflow(code, stack, mode);
+                gen_mask(code, mode);
+                code.mark(start, *span);
+            }
+            Ast::Not { span, expr } => {
+                gen_ast(expr, code, stack, mode)?;
+
+                let start = code.len();
+
+                i64_const(code, u64::max_value());
+                code.inst(&[0x85], "i64.xor");
+                gen_mask(code, mode);
+                code.mark(start, *span);
+            }
+            Ast::Group { expr, .. } => gen_ast(expr, code, stack, mode)?,
+            Ast::Let {
+                span,
+                name,
+                value,
+                body,
+            } => {
+                gen_ast(value, code, stack, mode)?;
+
+                let start = code.len();
+                let local = alloc(code, stack, Some(name.as_str()));
+
+                local_set(code, local);
+                code.mark(start, *span);
+                gen_ast(body, code, stack, mode)?;
+                stack.pop();
+            }
+            Ast::If {
+                span,
+                cond,
+                then,
+                else_,
+            } => {
+                gen_ast(cond, code, stack, mode)?;
+
+                let start = code.len();
+
+                code.inst(&[0xa7], "i32.wrap_i64");
+                code.inst(&[0x04, 0x7e], "if (result i64)");
+                code.mark(start, *span);
+                gen_ast(then, code, stack, mode)?;
+
+                let start = code.len();
+
+                code.inst(&[0x05], "else");
+                code.mark(start, *span);
+                gen_ast(else_, code, stack, mode)?;
+
+                let start = code.len();
+
+                code.inst(&[0x0b], "end");
+                code.mark(start, *span);
+            }
+            Ast::Var { span, name } => {
+                let start = code.len();
+
+                local_get(code, lookup_local(stack, name));
+                code.mark(start, *span);
+            }
+        }
+
+        Ok(())
+    })
+}
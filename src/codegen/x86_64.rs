@@ -0,0 +1,431 @@
+use super::{lookup, unsupported_call, unsupported_float, unsupported_string, Code, Stack, Trap};
+use crate::ast::*;
+use crate::run::{IntMode, Overflow};
+use diagnostics::{Diagnostic, Spanned};
+
+pub fn gen_program(program: &[Ast], code: &mut Code, mode: IntMode) -> Result<(), Diagnostic> {
+    for (i, ast) in program.iter().enumerate() {
+        gen_ast(ast, code, &mut Vec::new(), mode)?;
+
+        let start = code.len();
+
+        gen_print_u64(code);
+
+        // Only the last result is kept for the exit status.
+        if i + 1 < program.len() {
+            code.inst(&[0x58], "pop rax");
+        }
+
+        code.mark(start, ast.span());
+    }
+
+    if program.is_empty() {
+        code.inst(&[0x6a, 0x00], "push 0");
+    }
+
+    gen_exit(code);
+
+    gen_traps(code);
+
+    Ok(())
+}
+
+/// Writes the value on top of the stack to stdout in decimal, followed by a newline, leaving
+/// the value on the stack. The digits are built up below the stack pointer, which is safe as
+/// nothing is called.
+fn gen_print_u64(code: &mut Code) {
+    code.inst(&[0x48, 0x8b, 0x04, 0x24], "mov rax, [rsp]");
+    code.inst(&[0x48, 0x89, 0xe1], "mov rcx, rsp");
+    code.inst(&[0x48, 0xff, 0xc9], "dec rcx");
+    code.inst(&[0xc6, 0x01, 0x0a], "mov byte ptr [rcx], 10");
+    code.inst(&[0x49, 0xc7, 0xc0, 0x0a, 0x00, 0x00, 0x00], "mov r8, 10");
+    code.label("1");
+    code.inst(&[0x31, 0xd2], "xor edx, edx");
+    code.inst(&[0x49, 0xf7, 0xf0], "div r8");
+    code.inst(&[0x80, 0xc2, 0x30], "add dl, 48");
+    code.inst(&[0x48, 0xff, 0xc9], "dec rcx");
+    code.inst(&[0x88, 0x11], "mov [rcx], dl");
+    code.inst(&[0x48, 0x85, 0xc0], "test rax, rax");
+    code.inst(&[0x75, 0xee], "jnz 1b");
+    code.inst(&[0x48, 0x89, 0xe2], "mov rdx, rsp");
+    code.inst(&[0x48, 0x29, 0xca], "sub rdx, rcx");
+    code.inst(&[0x48, 0x89, 0xce], "mov rsi, rcx");
+    code.inst(&[0xbf, 0x01, 0x00, 0x00, 0x00], "mov edi, 1");
+    code.inst(&[0xb8, 0x01, 0x00, 0x00, 0x00], "mov eax, 1");
+    code.inst(&[0x0f, 0x05], "syscall");
+}
+
+/// Pops the result off the stack and exits with its low byte as the status. The kernel would
+/// truncate the status the same way, but masking makes the value passed explicit.
+fn gen_exit(code: &mut Code) {
+    code.inst(&[0x5f], "pop rdi");
+    code.inst(&[0x40, 0x0f, 0xb6, 0xff], "movzx edi, dil");
+    code.inst(&[0xb8, 0x3c, 0x00, 0x00, 0x00], "mov eax, 60");
+    code.inst(&[0x0f, 0x05], "syscall");
+}
+
+/// Truncates `rax` to the integer width. Writing a 32-bit register clears the upper half.
+fn gen_mask(code: &mut Code, mode: IntMode) {
+    match mode.width {
+        8 => code.inst(&[0x0f, 0xb6, 0xc0], "movzx eax, al"),
+        16 => code.inst(&[0x0f, 0xb7, 0xc0], "movzx eax, ax"),
+        32 => code.inst(&[0x89, 0xc0], "mov eax, eax"),
+        _ => {}
+    }
+}
+
+/// Emits a `jcc` with a 32-bit displacement to the routine for `trap`, `cc` being the
+/// second opcode byte. The displacement is filled in by `gen_traps`.
+fn gen_trap_branch(code: &mut Code, trap: Trap, cc: u8, jcc: &str) {
+    code.traps.push((code.len(), trap));
+    code.inst(
+        &[0x0f, cc, 0x00, 0x00, 0x00, 0x00],
+        format!("{} {}", jcc, trap.label()),
+    );
+}
+
+/// Branches to the division by zero routine if `r8`, the divisor, is 0.
+fn gen_check_divisor(code: &mut Code) {
+    code.inst(&[0x4d, 0x85, 0xc0], "test r8, r8");
+    gen_trap_branch(code, Trap::DivisionByZero, 0x84, "jz");
+}
+
+/// Branches to the overflow routine if the result in `rax` doesn't fit when trapping. At
+/// 64 bits the instruction producing the result must have set the carry flag on overflow,
+/// narrower results are computed in 64 bits and fit if their upper bits are clear.
+fn gen_check_overflow(code: &mut Code, mode: IntMode) {
+    if mode.overflow == Overflow::Wrap {
+        return;
+    }
+
+    if mode.width >= 64 {
+        gen_trap_branch(code, Trap::Overflow, 0x82, "jc");
+    } else {
+        code.inst(&[0x48, 0x89, 0xc1], "mov rcx, rax");
+        code.inst(
+            &[0x48, 0xc1, 0xe9, mode.width as u8],
+            format!("shr rcx, {}", mode.width),
+        );
+        gen_trap_branch(code, Trap::Overflow, 0x85, "jnz");
+    }
+}
+
+/// Points the 32-bit displacement at `at`, which ends the instruction, to the current end
+/// of the code.
+fn patch_rel32(code: &mut Code, at: usize) {
+    let rel = (code.len() as i32 - (at + 4) as i32).to_le_bytes();
+
+    code.bytes[at..at + 4].copy_from_slice(&rel);
+}
+
+/// Like `patch_rel32`, for an 8-bit displacement.
+fn patch_rel8(code: &mut Code, at: usize) {
+    code.bytes[at] = (code.len() as i32 - (at + 1) as i32) as u8;
+}
+
+/// Raises `rax` to the power of `r8` by squaring, leaving the result in `rax` and branching
+/// to the overflow routine if it doesn't fit. Both multiplications use `mul`, which sets
+/// the carry flag on overflow, and the base is only squared while bits of the exponent
+/// remain, as the final squaring may overflow without affecting the result.
+fn gen_checked_pow(code: &mut Code, mode: IntMode) {
+    code.inst(&[0x49, 0x89, 0xc1], "mov r9, rax");
+    code.inst(&[0x49, 0xc7, 0xc2, 0x01, 0x00, 0x00, 0x00], "mov r10, 1");
+
+    let start = code.len();
+
+    code.label("1");
+    code.inst(&[0x41, 0xf6, 0xc0, 0x01], "test r8b, 1");
+
+    let jz_square = code.len();
+
+    code.inst(&[0x74, 0x00], "jz 2f");
+    code.inst(&[0x4c, 0x89, 0xd0], "mov rax, r10");
+    code.inst(&[0x49, 0xf7, 0xe1], "mul r9");
+    gen_check_overflow(code, mode);
+    code.inst(&[0x49, 0x89, 0xc2], "mov r10, rax");
+    patch_rel8(code, jz_square + 1);
+    code.label("2");
+    code.inst(&[0x49, 0xd1, 0xe8], "shr r8, 1");
+
+    let jz_end = code.len();
+
+    code.inst(&[0x74, 0x00], "jz 3f");
+    code.inst(&[0x4c, 0x89, 0xc8], "mov rax, r9");
+    code.inst(&[0x49, 0xf7, 0xe1], "mul r9");
+    gen_check_overflow(code, mode);
+    code.inst(&[0x49, 0x89, 0xc1], "mov r9, rax");
+
+    let rel = (start as i32 - (code.len() + 2) as i32) as u8;
+
+    code.inst(&[0xeb, rel], "jmp 1b");
+    patch_rel8(code, jz_end + 1);
+    code.label("3");
+    code.inst(&[0x4c, 0x89, 0xd0], "mov rax, r10");
+}
+
+/// Places the routine of every trap branched to, pointing the branches at it.
+fn gen_traps(code: &mut Code) {
+    for routine in code.trap_routines() {
+        let start = code.len();
+
+        for &jcc in &routine.branches {
+            let rel = (start as i32 - (jcc + 6) as i32).to_le_bytes();
+
+            code.bytes[jcc + 2..jcc + 6].copy_from_slice(&rel);
+        }
+
+        let msg = &routine.message;
+        let mut mov = vec![0xba];
+
+        mov.extend(&(msg.len() as u32).to_le_bytes());
+        code.label(&routine.label);
+        code.inst(&mov, format!("mov edx, {}", msg.len()));
+        code.inst(
+            &[0x48, 0x8d, 0x35, 0x18, 0x00, 0x00, 0x00],
+            format!("lea rsi, [rip + {}_msg]", routine.label),
+        );
+        code.inst(&[0xbf, 0x02, 0x00, 0x00, 0x00], "mov edi, 2");
+        code.inst(&[0xb8, 0x01, 0x00, 0x00, 0x00], "mov eax, 1");
+        code.inst(&[0x0f, 0x05], "syscall");
+        code.inst(&[0xbf, 0x01, 0x00, 0x00, 0x00], "mov edi, 1");
+        code.inst(&[0xb8, 0x3c, 0x00, 0x00, 0x00], "mov eax, 60");
+        code.inst(&[0x0f, 0x05], "syscall");
+        code.label(&format!("{}_msg", routine.label));
+        code.inst(msg.as_bytes(), format!(".ascii {:?}", msg));
+    }
+}
+
+fn gen_ast<'a>(
+    ast: &'a Ast,
+    code: &mut Code,
+    stack: &mut Stack<'a>,
+    mode: IntMode,
+) -> Result<(), Diagnostic> {
+    crate::grow_stack(|| {
+        match ast {
+            Ast::Int { span, val } => {
+                let start = code.len();
+                let mut mov = vec![0x48, 0xb8];
+
+                mov.extend(&val.to_le_bytes());
+                code.inst(&mov, format!("movabs rax, {}", val));
+                code.inst(&[0x50], "push rax");
+                code.mark(start, *span);
+            }
+            Ast::Float { span, .. } => return Err(unsupported_float(*span)),
+            Ast::Str { span, .. } => return Err(unsupported_string(*span)),
+            Ast::Call { span, .. } => return Err(unsupported_call(*span)),
+            Ast::Error { .. } => unreachable!("analysis rejects programs with syntax errors"),
+            Ast::Op {
+                span,
+                op,
+                left,
+                right,
+                ..
+            } => {
+                gen_ast(left, code, stack, mode)?;
+                stack.push(None);
+                gen_ast(right, code, stack, mode)?;
+                stack.pop();
+
+                let start = code.len();
+
+                code.inst(&[0x41, 0x58], "pop r8");
+                code.inst(&[0x58], "pop rax");
+
+                match op {
+                    Op::Add => {
+                        code.inst(&[0x4c, 0x01, 0xc0], "add rax, r8");
+                        gen_check_overflow(code, mode);
+                        gen_mask(code, mode);
+                        code.inst(&[0x50], "push rax");
+                    }
+                    Op::Sub => {
+                        code.inst(&[0x4c, 0x29, 0xc0], "sub rax, r8");
+                        gen_check_overflow(code, mode);
+                        gen_mask(code, mode);
+                        code.inst(&[0x50], "push rax");
+                    }
+                    Op::Mul => {
+                        code.inst(&[0x49, 0xf7, 0xe0], "mul r8");
+                        gen_check_overflow(code, mode);
+                        gen_mask(code, mode);
+                        code.inst(&[0x50], "push rax");
+                    }
+                    // `div` (f7 /6) is the unsigned division, values are unsigned so `rdx` holds
+                    // the zero-extension of `rax` rather than its sign.
+                    Op::Div => {
+                        gen_check_divisor(code);
+                        code.inst(&[0x48, 0x31, 0xd2], "xor rdx, rdx");
+                        code.inst(&[0x49, 0xf7, 0xf0], "div r8");
+                        code.inst(&[0x50], "push rax");
+                    }
+                    Op::Mod => {
+                        gen_check_divisor(code);
+                        code.inst(&[0x48, 0x31, 0xd2], "xor rdx, rdx");
+                        code.inst(&[0x49, 0xf7, 0xf0], "div r8");
+                        code.inst(&[0x52], "push rdx");
+                    }
+                    // Exponentiation by squaring, multiplying the base into the result for
+                    // every set bit of the exponent. An exponent of 0 leaves the result at 1.
+                    // The final squaring may overflow without affecting the result, which
+                    // doesn't matter when wrapping.
+                    Op::Pow if mode.overflow == Overflow::Wrap => {
+                        code.inst(&[0x48, 0xc7, 0xc1, 0x01, 0x00, 0x00, 0x00], "mov rcx, 1");
+                        code.label("1");
+                        code.inst(&[0x41, 0xf6, 0xc0, 0x01], "test r8b, 1");
+                        code.inst(&[0x74, 0x04], "jz 2f");
+                        code.inst(&[0x48, 0x0f, 0xaf, 0xc8], "imul rcx, rax");
+                        code.label("2");
+                        code.inst(&[0x48, 0x0f, 0xaf, 0xc0], "imul rax, rax");
+                        code.inst(&[0x49, 0xd1, 0xe8], "shr r8, 1");
+                        code.inst(&[0x75, 0xed], "jnz 1b");
+                        code.inst(&[0x48, 0x89, 0xc8], "mov rax, rcx");
+                        gen_mask(code, mode);
+                        code.inst(&[0x50], "push rax");
+                    }
+                    Op::Pow => {
+                        gen_checked_pow(code, mode);
+                        code.inst(&[0x50], "push rax");
+                    }
+                    Op::BitAnd => {
+                        code.inst(&[0x4c, 0x21, 0xc0], "and rax, r8");
+                        code.inst(&[0x50], "push rax");
+                    }
+                    Op::BitOr => {
+                        code.inst(&[0x4c, 0x09, 0xc0], "or rax, r8");
+                        code.inst(&[0x50], "push rax");
+                    }
+                    Op::BitXor => {
+                        code.inst(&[0x4c, 0x31, 0xc0], "xor rax, r8");
+                        code.inst(&[0x50], "push rax");
+                    }
+                }
+
+                code.mark(start, *span);
+            }
+            Ast::Cmp {
+                span,
+                op,
+                left,
+                right,
+            } => {
+                gen_ast(left, code, stack, mode)?;
+                stack.push(None);
+                gen_ast(right, code, stack, mode)?;
+                stack.pop();
+
+                let start = code.len();
+
+                code.inst(&[0x41, 0x58], "pop r8");
+                code.inst(&[0x58], "pop rax");
+                code.inst(&[0x4c, 0x39, 0xc0], "cmp rax, r8");
+
+                // Values are unsigned, so the below/above conditions are used.
+                match op {
+                    CmpOp::Lt => code.inst(&[0x0f, 0x92, 0xc0], "setb al"),
+                    CmpOp::Gt => code.inst(&[0x0f, 0x97, 0xc0], "seta al"),
+                    CmpOp::Le => code.inst(&[0x0f, 0x96, 0xc0], "setbe al"),
+                    CmpOp::Ge => code.inst(&[0x0f, 0x93, 0xc0], "setae al"),
+                    CmpOp::Eq => code.inst(&[0x0f, 0x94, 0xc0], "sete al"),
+                    CmpOp::Ne => code.inst(&[0x0f, 0x95, 0xc0], "setne al"),
+                }
+
+                code.inst(&[0x0f, 0xb6, 0xc0], "movzx eax, al");
+                code.inst(&[0x50], "push rax");
+                code.mark(start, *span);
+            }
+            Ast::Neg { span, expr } => {
+                gen_ast(expr, code, stack, mode)?;
+
+                let start = code.len();
+
+                code.inst(&[0x58], "pop rax");
+                // `neg` sets the carry flag for anything but 0, like `0 - rax` would.
+                code.inst(&[0x48, 0xf7, 0xd8], "neg rax");
+                gen_check_overflow(code, mode);
+                gen_mask(code, mode);
+                code.inst(&[0x50], "push rax");
+                code.mark(start, *span);
+            }
+            Ast::Not { span, expr } => {
+                gen_ast(expr, code, stack, mode)?;
+
+                let start = code.len();
+
+                code.inst(&[0x58], "pop rax");
+                code.inst(&[0x48, 0xf7, 0xd0], "not rax");
+                gen_mask(code, mode);
+                code.inst(&[0x50], "push rax");
+                code.mark(start, *span);
+            }
+            Ast::Group { expr, .. } => gen_ast(expr, code, stack, mode)?,
+            Ast::Let {
+                span,
+                name,
+                value,
+                body,
+            } => {
+                gen_ast(value, code, stack, mode)?;
+                stack.push(Some(name.as_str()));
+                gen_ast(body, code, stack, mode)?;
+                stack.pop();
+
+                // Replace the binding with the result of the body.
+                let start = code.len();
+
+                code.inst(&[0x58], "pop rax");
+                code.inst(&[0x48, 0x89, 0x04, 0x24], "mov [rsp], rax");
+                code.mark(start, *span);
+            }
+            // Each branch leaves its result where the condition was.
+            Ast::If {
+                span,
+                cond,
+                then,
+                else_,
+            } => {
+                gen_ast(cond, code, stack, mode)?;
+
+                let (else_label, end_label) = code.conditional_labels();
+                let start = code.len();
+
+                code.inst(&[0x58], "pop rax");
+                code.inst(&[0x48, 0x85, 0xc0], "test rax, rax");
+
+                let jz = code.len();
+
+                code.inst(
+                    &[0x0f, 0x84, 0x00, 0x00, 0x00, 0x00],
+                    format!("jz {}", else_label),
+                );
+                code.mark(start, *span);
+                gen_ast(then, code, stack, mode)?;
+
+                let jmp = code.len();
+
+                code.inst(
+                    &[0xe9, 0x00, 0x00, 0x00, 0x00],
+                    format!("jmp {}", end_label),
+                );
+                code.mark(jmp, *span);
+                patch_rel32(code, jz + 2);
+                code.label(&else_label);
+                gen_ast(else_, code, stack, mode)?;
+                patch_rel32(code, jmp + 1);
+                code.label(&end_label);
+            }
+            Ast::Var { span, name } => {
+                let start = code.len();
+                let offset = lookup(stack, name) * 8;
+                let mut push = vec![0xff, 0xb4, 0x24];
+
+                push.extend(&(offset as u32).to_le_bytes());
+                code.inst(&push, format!("push qword ptr [rsp + {}]", offset));
+                code.mark(start, *span);
+            }
+        }
+
+        Ok(())
+    })
+}
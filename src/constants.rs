@@ -0,0 +1,192 @@
+//! The constant subexpressions of a program, found once and shared by analysis, code
+//! generation and evaluation.
+
+use crate::ast::*;
+use crate::run::{self, IntMode, Value};
+use diagnostics::{Span, Spanned};
+use std::collections::HashMap;
+
+/// The value of every constant integer node of a program, keyed by the node's span. A node
+/// keeps its span when the tree is moved or its groups are stripped, so the table stays
+/// valid for both. The only nodes sharing a span are the `let`s `--define` wraps around an
+/// expression, which evaluate to that expression.
+#[derive(Debug, Default, Clone)]
+pub struct Constants {
+    values: HashMap<Span, u64>,
+}
+
+impl Constants {
+    pub fn new() -> Constants {
+        Constants::default()
+    }
+
+    /// Finds every constant integer subexpression of `program` in a single pass. Operations
+    /// that would fail at run time, such as a division by zero, aren't constant.
+    pub fn find(program: &[Ast], mode: IntMode) -> Constants {
+        let mut constants = Constants::new();
+
+        for ast in program {
+            constants.find_in(ast, &mut HashMap::new(), mode);
+        }
+
+        constants
+    }
+
+    /// The value of `ast`, if it is constant.
+    pub fn get(&self, ast: &Ast) -> Option<u64> {
+        self.values.get(&ast.span()).copied()
+    }
+
+    /// Records the value of `ast` and its subexpressions that are constant and returns that
+    /// of `ast`. `env` holds the variables currently bound to a constant.
+    fn find_in<'a>(
+        &mut self,
+        ast: &'a Ast,
+        env: &mut HashMap<&'a str, u64>,
+        mode: IntMode,
+    ) -> Option<u64> {
+        crate::grow_stack(|| {
+            let val = match ast {
+                // Literals that don't fit are truncated, as they are at run time.
+                Ast::Int { val, .. } => Some(mode.mask(*val)),
+                Ast::Float { .. } | Ast::Str { .. } | Ast::Error { .. } => None,
+                Ast::Op {
+                    op, left, right, ..
+                } => {
+                    let left = self.find_in(left, env, mode);
+                    let right = self.find_in(right, env, mode);
+
+                    match (left, right) {
+                        (Some(left), Some(right)) => run::apply_int(*op, left, right, mode).ok(),
+                        _ => None,
+                    }
+                }
+                // Booleans aren't folded, they would turn into integers.
+                Ast::Cmp { left, right, .. } => {
+                    self.find_in(left, env, mode);
+                    self.find_in(right, env, mode);
+                    None
+                }
+                Ast::Neg { expr, .. } => self
+                    .find_in(expr, env, mode)
+                    .and_then(|val| run::apply_int(Op::Sub, 0, val, mode).ok()),
+                Ast::Not { expr, .. } => self.find_in(expr, env, mode).map(|val| mode.mask(!val)),
+                Ast::Group { expr, .. } => self.find_in(expr, env, mode),
+                Ast::Let {
+                    name, value, body, ..
+                } => {
+                    let value = self.find_in(value, env, mode);
+                    // A binding that isn't constant still shadows an outer constant of the same
+                    // name.
+                    let outer = match value {
+                        Some(val) => env.insert(name, val),
+                        None => env.remove(name.as_str()),
+                    };
+                    let body = self.find_in(body, env, mode);
+
+                    match outer {
+                        Some(outer) => env.insert(name, outer),
+                        None => env.remove(name.as_str()),
+                    };
+
+                    body
+                }
+                // Conditions are comparisons, which aren't folded, so both branches are kept.
+                Ast::If {
+                    cond, then, else_, ..
+                } => {
+                    self.find_in(cond, env, mode);
+                    self.find_in(then, env, mode);
+                    self.find_in(else_, env, mode);
+                    None
+                }
+                Ast::Var { name, .. } => env.get(name.as_str()).copied(),
+                Ast::Call { name, args, .. } => {
+                    // Every argument is visited, even after one that isn't constant.
+                    let args = args
+                        .iter()
+                        .map(|arg| self.find_in(arg, env, mode))
+                        .collect::<Vec<_>>();
+                    let values = args
+                        .into_iter()
+                        .map(|arg| arg.map(Value::Int))
+                        .collect::<Option<Vec<_>>>();
+
+                    match values.map(|values| run::call(name, &values, mode)) {
+                        Some(Ok(Value::Int(val))) => Some(val),
+                        _ => None,
+                    }
+                }
+            };
+
+            if let Some(val) = val {
+                self.values.insert(ast.span(), val);
+            }
+
+            val
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_test;
+
+    #[test]
+    fn constants_survive_stripping_groups() {
+        let program = parse_test("((1 + 2)) * 3; 4 - (5 - 5)").expect("syntax error");
+        let constants = Constants::find(&program, IntMode::default());
+        let program = program
+            .into_iter()
+            .map(Ast::strip_groups)
+            .collect::<Vec<_>>();
+
+        assert_eq!(constants.get(&program[0]), Some(9));
+        assert_eq!(constants.get(&program[1]), Some(4));
+
+        match &program[0] {
+            Ast::Op { left, .. } => assert_eq!(constants.get(left), Some(3)),
+            ast => panic!("parsed to {}", ast),
+        }
+    }
+
+    #[test]
+    fn many_copies_of_a_subexpression() {
+        let copies = 10_000;
+        let source = vec!["(1 + 2) * 3"; copies].join(" + ");
+        let program = parse_test(&source).expect("syntax error");
+        let constants = Constants::find(&program, IntMode::default());
+
+        assert_eq!(constants.get(&program[0]), Some(9 * copies as u64));
+
+        // Every copy is a node of its own, with a value of its own.
+        let mut ast = &program[0];
+        let mut checked = 0;
+
+        while let Ast::Op {
+            op: Op::Add,
+            left,
+            right,
+            ..
+        } = ast
+        {
+            assert_eq!(constants.get(right), Some(9));
+            ast = left;
+            checked += 1;
+        }
+
+        assert_eq!(checked, copies - 1);
+        assert_eq!(constants.get(ast), Some(9));
+    }
+
+    #[test]
+    fn failing_operations_are_not_constant() {
+        let program = parse_test("1 / 0; 0 - 1; x + 1").expect("syntax error");
+        let constants = Constants::find(&program, IntMode::default());
+
+        for ast in &program {
+            assert_eq!(constants.get(ast), None);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Prints programs back as source in a canonical layout. Parentheses are placed where
+//! precedence requires them rather than where the source had them, so redundant ones are
+//! dropped and the output parses back into the same tree, minus its `Group`s.
+
+use crate::ast::*;
+use std::fmt::Write;
+
+/// Binding strength of every kind of node, from loosest to tightest.
+const LET: u8 = 0;
+const CMP: u8 = 4;
+const NEG: u8 = 7;
+const ATOM: u8 = 9;
+
+pub fn format(program: &[Ast]) -> String {
+    let mut out = String::new();
+
+    for (i, ast) in program.iter().enumerate() {
+        if i > 0 {
+            out.push_str(";\n");
+        }
+
+        write_ast(&mut out, ast, LET);
+    }
+
+    out.push('\n');
+    out
+}
+
+fn precedence(ast: &Ast) -> u8 {
+    match ast {
+        Ast::Int { .. }
+        | Ast::Float { .. }
+        | Ast::Str { .. }
+        | Ast::Var { .. }
+        | Ast::Call { .. }
+        | Ast::Error { .. } => ATOM,
+        Ast::Op { op, .. } => op_precedence(*op),
+        Ast::Cmp { .. } => CMP,
+        Ast::Neg { .. } | Ast::Not { .. } => NEG,
+        Ast::Group { expr, .. } => precedence(expr),
+        Ast::Let { .. } | Ast::If { .. } => LET,
+    }
+}
+
+/// Comparisons sit between the bitwise operators and `+`, negation between `*` and `**`.
+fn op_precedence(op: Op) -> u8 {
+    match op {
+        Op::BitOr => 1,
+        Op::BitXor => 2,
+        Op::BitAnd => 3,
+        Op::Add | Op::Sub => 5,
+        Op::Mul | Op::Div | Op::Mod => 6,
+        Op::Pow => 8,
+    }
+}
+
+/// Writes `val` as a string literal. `{:?}` would escape characters the lexer doesn't know,
+/// so only the escapes it accepts are used.
+fn write_str(out: &mut String, val: &str) {
+    out.push('"');
+
+    for ch in val.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\0' => out.push_str("\\0"),
+            ch => out.push(ch),
+        }
+    }
+
+    out.push('"');
+}
+
+/// Writes `ast`, parenthesized if it binds looser than `min`.
+fn write_ast(out: &mut String, ast: &Ast, min: u8) {
+    crate::grow_stack(|| {
+        // The body of a `let` and the `else` branch extend as far as possible, so they always
+        // need parentheses when they are an operand.
+        let parens = precedence(ast) < min || min > LET && precedence(ast) == LET;
+
+        if parens {
+            out.push('(');
+            write_ast(out, ast, LET);
+            out.push(')');
+            return;
+        }
+
+        match ast {
+            Ast::Int { val, .. } => write!(out, "{}", val).unwrap(),
+            // `{:?}` may use an exponent without a sign, which the lexer doesn't accept.
+            Ast::Float { val, .. } => {
+                let float = val.to_string();
+
+                out.push_str(&float);
+
+                if !float.contains('.') {
+                    out.push_str(".0");
+                }
+            }
+            Ast::Str { val, .. } => write_str(out, val),
+            Ast::Var { name, .. } => out.push_str(name),
+            Ast::Error { .. } => out.push_str("<error>"),
+            Ast::Call { name, args, .. } => {
+                write!(out, "{}(", name).unwrap();
+
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+
+                    write_ast(out, arg, LET);
+                }
+
+                out.push(')');
+            }
+            // The base of `**` is an atom while its exponent may be negated.
+            Ast::Op {
+                op: Op::Pow,
+                left,
+                right,
+                ..
+            } => {
+                write_ast(out, left, ATOM);
+                out.push_str(" ** ");
+                write_ast(out, right, NEG);
+            }
+            Ast::Op {
+                op, left, right, ..
+            } => {
+                let prec = op_precedence(*op);
+
+                write_ast(out, left, prec);
+                write!(out, " {} ", op).unwrap();
+                write_ast(out, right, prec + 1);
+            }
+            Ast::Cmp {
+                op, left, right, ..
+            } => {
+                write_ast(out, left, CMP);
+                write!(out, " {} ", op).unwrap();
+                write_ast(out, right, CMP + 1);
+            }
+            Ast::Neg { expr, .. } => {
+                out.push('-');
+                write_ast(out, expr, NEG);
+            }
+            Ast::Not { expr, .. } => {
+                out.push('~');
+                write_ast(out, expr, NEG);
+            }
+            Ast::Group { expr, .. } => write_ast(out, expr, min),
+            Ast::Let {
+                name, value, body, ..
+            } => {
+                write!(out, "let {} = ", name).unwrap();
+                write_ast(out, value, LET);
+                out.push_str(" in ");
+                write_ast(out, body, LET);
+            }
+            Ast::If {
+                cond, then, else_, ..
+            } => {
+                out.push_str("if ");
+                write_ast(out, cond, LET);
+                out.push_str(" then ");
+                write_ast(out, then, LET);
+                out.push_str(" else ");
+                write_ast(out, else_, LET);
+            }
+        }
+    })
+}
@@ -0,0 +1,161 @@
+//! The compiler as a library. The functions here take a program as a source string and run
+//! every phase up to the one they are named after, the modules give finer control.
+
+pub mod analysis;
+pub mod ast;
+pub mod codegen;
+pub mod constants;
+pub mod format;
+pub mod parsing;
+pub mod run;
+pub mod simplify;
+
+use ast::Ast;
+use constants::Constants;
+use diagnostics::{FileId, FileInfo, FileInterner, Reporter, Span};
+use intern::Intern;
+use run::Value;
+
+/// A successful result along with the warnings reported while producing it.
+pub struct Output<T> {
+    pub value: T,
+    pub warnings: Reporter,
+}
+
+/// On failure the reporter holds every diagnostic, warnings included. Either way they are
+/// printed with `Reporter::report`.
+pub type Result<T> = std::result::Result<Output<T>, Reporter>;
+
+pub fn parse(files: &FileInterner, name: &str, source: &str) -> Result<Vec<Ast>> {
+    let reporter = Reporter::default();
+    let (program, errors) = parsing::parse(&reporter, intern(files, name, source));
+
+    if errors == 0 {
+        Ok(Output {
+            value: program,
+            warnings: reporter,
+        })
+    } else {
+        Err(reporter)
+    }
+}
+
+/// Parses and analyzes the program. Groups are left out of the result, they only matter
+/// for the redundant parentheses lint.
+pub fn analyze(
+    files: &FileInterner,
+    name: &str,
+    source: &str,
+    options: analysis::Options,
+) -> Result<Vec<Ast>> {
+    let reporter = Reporter::default();
+
+    match check(&reporter, intern(files, name, source), options) {
+        Some((program, _)) => Ok(Output {
+            value: program,
+            warnings: reporter,
+        }),
+        None => Err(reporter),
+    }
+}
+
+/// Evaluates every statement in the program, stopping at the first run time error. Each
+/// value is paired with the span of its statement.
+pub fn run(
+    files: &FileInterner,
+    name: &str,
+    source: &str,
+    options: analysis::Options,
+) -> Result<Vec<(Span, Value)>> {
+    let reporter = Reporter::default();
+    let (program, constants) = match check(&reporter, intern(files, name, source), options) {
+        Some(checked) => checked,
+        None => return Err(reporter),
+    };
+
+    match run::run_top_level(&program, &constants, options.int_mode) {
+        Ok(values) => Ok(Output {
+            value: values,
+            warnings: reporter,
+        }),
+        Err(e) => {
+            reporter.add(e.into());
+            Err(reporter)
+        }
+    }
+}
+
+/// Compiles the program to `output` in the form chosen by `codegen_options`.
+pub fn compile(
+    files: &FileInterner,
+    name: &str,
+    source: &str,
+    output: &str,
+    codegen_options: &codegen::Options,
+    options: analysis::Options,
+) -> Result<()> {
+    let reporter = Reporter::default();
+    let (program, constants) = match check(&reporter, intern(files, name, source), options) {
+        Some(checked) => checked,
+        None => return Err(reporter),
+    };
+
+    match codegen::compile(&program, &constants, output, codegen_options) {
+        Ok(()) => Ok(Output {
+            value: (),
+            warnings: reporter,
+        }),
+        Err(e) => {
+            reporter.add(e);
+            Err(reporter)
+        }
+    }
+}
+
+/// Runs `f`, first switching to a new stack segment if the current one is nearly used up.
+/// Every recursive walk over the tree goes through here, so that deeply nested programs
+/// don't overflow the stack.
+pub(crate) fn grow_stack<R>(f: impl FnOnce() -> R) -> R {
+    const RED_ZONE: usize = 64 * 1024;
+    const SEGMENT_SIZE: usize = 1024 * 1024;
+
+    stacker::maybe_grow(RED_ZONE, SEGMENT_SIZE, f)
+}
+
+fn intern(files: &FileInterner, name: &str, source: &str) -> FileId {
+    FileInfo {
+        source: source.to_string(),
+        name: name.into(),
+    }
+    .intern(files)
+}
+
+/// Parses and analyzes `file`, returning the program and its constant subexpressions if
+/// there were no errors. Groups are stripped once analysis is done with them, the
+/// constants are keyed by span and so still apply.
+fn check(
+    reporter: &Reporter,
+    file: FileId,
+    options: analysis::Options,
+) -> Option<(Vec<Ast>, Constants)> {
+    let (program, errors) = parsing::parse(reporter, file);
+    let constants = Constants::find(&program, options.int_mode);
+
+    // Analysis still runs after a syntax error so that all errors are found at once.
+    if analysis::analyze(reporter, &program, &constants, options) && errors == 0 {
+        Some((
+            program.into_iter().map(Ast::strip_groups).collect(),
+            constants,
+        ))
+    } else {
+        None
+    }
+}
+
+/// Parses `source` for a test, `None` if it has a syntax error.
+#[cfg(test)]
+pub(crate) fn parse_test(source: &str) -> Option<Vec<Ast>> {
+    parse(&FileInterner::new(), "<test>", source)
+        .ok()
+        .map(|output| output.value)
+}
@@ -1,12 +1,11 @@
-mod analysis;
-mod ast;
-mod codegen;
-mod parsing;
-mod run;
+mod profile;
 
-use clap::{App, Arg, SubCommand};
-use diagnostics::{FileInfo, FileInterner, Reporter};
-use intern::Intern;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use diagnostics::{Diagnostic, FileInfo, Reporter, Severity, Spanned};
+use math_lang::constants::Constants;
+use math_lang::{analysis, ast, codegen, format, parsing, run, simplify};
+use std::io::Read;
+use target_lexicon::Triple;
 
 fn main() {
     let matches = App::new("Math lang")
@@ -14,11 +13,93 @@ fn main() {
         .subcommand(
             SubCommand::with_name("build")
                 .arg(Arg::with_name("input").takes_value(true).required(true))
-                .arg(Arg::with_name("output").takes_value(true).required(true)),
+                .arg(Arg::with_name("output").takes_value(true).required(true))
+                .arg(Arg::with_name("listing").long("listing").takes_value(true))
+                .arg(Arg::with_name("target").long("target").takes_value(true))
+                .arg(
+                    Arg::with_name("linker")
+                        .long("linker")
+                        .takes_value(true)
+                        .default_value("ld"),
+                )
+                .arg(Arg::with_name("keep-obj").long("keep-obj"))
+                .arg(
+                    Arg::with_name("emit")
+                        .long("emit")
+                        .takes_value(true)
+                        .possible_values(&["obj", "asm", "bin", "ast-json", "llvm-ir"])
+                        .default_value("bin"),
+                )
+                .arg(Arg::with_name("warn-redundant-parens").long("warn-redundant-parens"))
+                .arg(
+                    Arg::with_name("int-width")
+                        .long("int-width")
+                        .takes_value(true)
+                        .possible_values(&["8", "16", "32", "64"])
+                        .default_value("64"),
+                )
+                .arg(
+                    Arg::with_name("overflow")
+                        .long("overflow")
+                        .takes_value(true)
+                        .possible_values(&["trap", "wrap"])
+                        .default_value("trap"),
+                )
+                .arg(
+                    Arg::with_name("define")
+                        .long("define")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(Arg::with_name("dump-tokens").long("dump-tokens"))
+                .arg(Arg::with_name("profile-alloc").long("profile-alloc"))
+                .arg(Arg::with_name("time").long("time")),
         )
         .subcommand(
             SubCommand::with_name("run")
-                .arg(Arg::with_name("input").takes_value(true).required(true)),
+                .arg(Arg::with_name("input").takes_value(true).required(true))
+                .arg(Arg::with_name("warn-redundant-parens").long("warn-redundant-parens"))
+                .arg(
+                    Arg::with_name("int-width")
+                        .long("int-width")
+                        .takes_value(true)
+                        .possible_values(&["8", "16", "32", "64"])
+                        .default_value("64"),
+                )
+                .arg(
+                    Arg::with_name("overflow")
+                        .long("overflow")
+                        .takes_value(true)
+                        .possible_values(&["trap", "wrap"])
+                        .default_value("trap"),
+                )
+                .arg(
+                    Arg::with_name("define")
+                        .long("define")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(Arg::with_name("dump-tokens").long("dump-tokens"))
+                .arg(Arg::with_name("trace").long("trace"))
+                .arg(Arg::with_name("profile-alloc").long("profile-alloc"))
+                .arg(Arg::with_name("time").long("time")),
+        )
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .arg(Arg::with_name("input").takes_value(true).required(true))
+                .arg(Arg::with_name("normalize").long("normalize"))
+                .arg(
+                    Arg::with_name("unsafe-math")
+                        .long("unsafe-math")
+                        .requires("normalize"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .arg(Arg::with_name("input").takes_value(true).required(true))
+                .arg(Arg::with_name("warn-redundant-parens").long("warn-redundant-parens")),
         )
         .get_matches();
 
@@ -26,20 +107,177 @@ fn main() {
         let input = matches.value_of("input").unwrap();
         let output = matches.value_of("output").unwrap();
 
-        cmd_build(input, output);
+        if matches.value_of("emit") == Some("ast-json") {
+            cmd_ast_json(input, output);
+            return;
+        }
+
+        cmd_build(
+            input,
+            output,
+            codegen_options(matches),
+            analysis_options(matches),
+            profile_options(matches),
+            &definitions(matches),
+            matches.is_present("dump-tokens"),
+        );
     } else if let Some(matches) = matches.subcommand_matches("run") {
         let input = matches.value_of("input").unwrap();
 
-        cmd_run(input);
+        cmd_run(
+            input,
+            analysis_options(matches),
+            profile_options(matches),
+            &definitions(matches),
+            matches.is_present("dump-tokens"),
+            matches.is_present("trace"),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("fmt") {
+        cmd_fmt(
+            matches.value_of("input").unwrap(),
+            matches.is_present("normalize"),
+            matches.is_present("unsafe-math"),
+        );
+    } else if let Some(matches) = matches.subcommand_matches("watch") {
+        let input = matches.value_of("input").unwrap();
+
+        cmd_watch(input, matches.is_present("warn-redundant-parens"));
     } else {
         println!("{}", matches.usage());
     }
 }
 
-fn cmd_build(input: &str, output: &str) {
+fn analysis_options(matches: &ArgMatches) -> analysis::Options {
+    analysis::Options {
+        redundant_parens: matches.is_present("warn-redundant-parens"),
+        int_mode: int_mode(matches),
+    }
+}
+
+fn codegen_options<'a>(matches: &'a ArgMatches) -> codegen::Options<'a> {
+    codegen::Options {
+        target: target(matches),
+        listing: matches.value_of("listing"),
+        emit: match matches.value_of("emit").unwrap() {
+            "obj" => codegen::Emit::Obj,
+            "asm" => codegen::Emit::Asm,
+            "llvm-ir" => codegen::Emit::LlvmIr,
+            _ => codegen::Emit::Bin,
+        },
+        linker: matches.value_of("linker").unwrap(),
+        keep_obj: matches.is_present("keep-obj"),
+        int_mode: int_mode(matches),
+    }
+}
+
+fn int_mode(matches: &ArgMatches) -> run::IntMode {
+    run::IntMode {
+        width: matches.value_of("int-width").unwrap().parse().unwrap(),
+        overflow: match matches.value_of("overflow").unwrap() {
+            "wrap" => run::Overflow::Wrap,
+            _ => run::Overflow::Trap,
+        },
+    }
+}
+
+fn target(matches: &ArgMatches) -> Triple {
+    match matches.value_of("target") {
+        Some(target) => target.parse().unwrap_or_else(|e| {
+            eprintln!("Invalid target `{}`: {}", target, e);
+            std::process::exit(1);
+        }),
+        None => target_lexicon::HOST,
+    }
+}
+
+fn profile_options(matches: &ArgMatches) -> profile::Options {
+    let alloc = matches.is_present("profile-alloc");
+
+    if alloc && !profile::available() {
+        eprintln!("--profile-alloc requires building with the `profile-alloc` feature");
+        std::process::exit(1);
+    }
+
+    profile::Options {
+        alloc,
+        time: matches.is_present("time"),
+    }
+}
+
+/// The constants given with `--define name=value`, in order. Each value is an expression,
+/// which may refer to the constants defined before it.
+fn definitions(matches: &ArgMatches) -> Vec<(String, ast::Ast)> {
+    let mut definitions = Vec::new();
+
+    for definition in matches.values_of("define").into_iter().flatten() {
+        let invalid = |msg: &str| -> ! {
+            eprintln!("Invalid definition `{}`: {}", definition, msg);
+            std::process::exit(1);
+        };
+        let eq = definition
+            .find('=')
+            .unwrap_or_else(|| invalid("expected `name=value`"));
+        let name = &definition[..eq];
+
+        if !name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        {
+            invalid("the name must be an identifier");
+        }
+
+        let (mut value, reporter) =
+            parsing::parse_str(&definition[eq + 1..], &format!("--define {}", name));
+
+        reporter.report(true);
+
+        if value.len() != 1 {
+            invalid("the value must be a single expression");
+        }
+
+        definitions.push((name.to_string(), value.remove(0)));
+    }
+
+    definitions
+}
+
+/// Binds `definitions` around `ast`, the first outermost.
+fn define(ast: ast::Ast, definitions: &[(String, ast::Ast)]) -> ast::Ast {
+    definitions
+        .iter()
+        .rev()
+        .fold(ast, |body, (name, value)| ast::Ast::Let {
+            span: body.span(),
+            name: name.clone(),
+            value: Box::new(value.clone()),
+            body: Box::new(body),
+        })
+}
+
+/// Reports an error that isn't about the program, such as failing to write the output, and
+/// exits.
+fn fail(msg: String) -> ! {
     let reporter = Reporter::default();
-    let files = FileInterner::new();
-    let file = if let Ok(source) = std::fs::read_to_string(input) {
+
+    reporter.add(Diagnostic::new(Severity::Error, None, msg));
+    reporter.report(true);
+    std::process::exit(1)
+}
+
+/// Reads the program from stdin when `input` is `-`, from the file `input` if it exists
+/// and otherwise treats `input` itself as the source.
+fn read_input(input: &str) -> FileInfo {
+    if input == "-" {
+        let mut source = String::new();
+
+        if let Err(e) = std::io::stdin().read_to_string(&mut source) {
+            fail(format!("Cannot read the program from stdin: {}", e));
+        }
+
+        FileInfo {
+            source,
+            name: "<stdin>".into(),
+        }
+    } else if let Ok(source) = std::fs::read_to_string(input) {
         FileInfo {
             source,
             name: input.into(),
@@ -50,47 +288,240 @@ fn cmd_build(input: &str, output: &str) {
             name: "<input>".into(),
         }
     }
-    .intern(&files);
+}
+
+fn cmd_build(
+    input: &str,
+    output: &str,
+    codegen_options: codegen::Options,
+    options: analysis::Options,
+    profile: profile::Options,
+    definitions: &[(String, ast::Ast)],
+    dump_tokens: bool,
+) {
+    let file = read_input(input);
+
+    if dump_tokens {
+        eprint!("{}", parsing::dump_tokens(&file.source, &file.name));
+    }
+
+    // Lexing is included in the parse time.
+    let (program, reporter) = profile::measure(profile, "parse", || {
+        parsing::parse_str(&file.source, &file.name)
+    });
+    let program = program
+        .into_iter()
+        .map(|ast| define(ast, definitions))
+        .collect::<Vec<_>>();
+
+    let constants = Constants::find(&program, options.int_mode);
+    let valid = profile::measure(profile, "analyze", || {
+        analysis::analyze(&reporter, &program, &constants, options)
+    });
 
-    match parsing::parse(&reporter, file) {
-        Err(e) => {
+    // Syntax errors are in the reporter as well, and are reported along with those found
+    // during analysis.
+    reporter.report(true);
+
+    // Groups only matter to analysis. The constants are keyed by span, which stripping
+    // leaves alone.
+    let program = program
+        .into_iter()
+        .map(ast::Ast::strip_groups)
+        .collect::<Vec<_>>();
+
+    if valid {
+        if let Err(e) = profile::measure(profile, "codegen", || {
+            codegen::compile(&program, &constants, output, &codegen_options)
+        }) {
             reporter.add(e);
             reporter.report(true);
         }
-        Ok(ast) => {
-            analysis::analyze(&reporter, &ast);
-            codegen::compile(&ast, output);
-        }
     }
 }
 
-fn cmd_run(input: &str) {
-    let reporter = Reporter::default();
-    let files = FileInterner::new();
-    let file = if let Ok(source) = std::fs::read_to_string(input) {
-        FileInfo {
-            source,
-            name: input.into(),
+/// Writes the parsed program as JSON to `output`, or stdout if it is `-`. Nothing beyond
+/// parsing is checked.
+fn cmd_ast_json(input: &str, output: &str) {
+    let file = read_input(input);
+    let (program, reporter) = parsing::parse_str(&file.source, &file.name);
+
+    reporter.report(true);
+
+    let json = serde_json::to_string_pretty(&program)
+        .unwrap_or_else(|e| fail(format!("Cannot serialize the program: {}", e)));
+
+    if output == "-" {
+        println!("{}", json);
+    } else if let Err(e) = std::fs::write(output, json) {
+        fail(format!("Cannot write `{}`: {}", output, e));
+    }
+}
+
+fn cmd_run(
+    input: &str,
+    options: analysis::Options,
+    profile: profile::Options,
+    definitions: &[(String, ast::Ast)],
+    dump_tokens: bool,
+    trace: bool,
+) {
+    let file = read_input(input);
+
+    if dump_tokens {
+        eprint!("{}", parsing::dump_tokens(&file.source, &file.name));
+    }
+
+    // Lexing is included in the parse time.
+    let (program, reporter) = profile::measure(profile, "parse", || {
+        parsing::parse_str(&file.source, &file.name)
+    });
+    let program = program
+        .into_iter()
+        .map(|ast| define(ast, definitions))
+        .collect::<Vec<_>>();
+
+    let constants = Constants::find(&program, options.int_mode);
+    let valid = profile::measure(profile, "analyze", || {
+        analysis::analyze(&reporter, &program, &constants, options)
+    });
+
+    // Syntax errors are in the reporter as well, and are reported along with those found
+    // during analysis.
+    reporter.report(true);
+
+    // Groups only matter to analysis. The constants are keyed by span, which stripping
+    // leaves alone.
+    let program = program
+        .into_iter()
+        .map(ast::Ast::strip_groups)
+        .collect::<Vec<_>>();
+
+    if valid {
+        for ast in &program {
+            let result = profile::measure(profile, "run", || {
+                if trace {
+                    run::run_traced(ast, options.int_mode, &mut print_trace)
+                } else {
+                    run::run(ast, &constants, options.int_mode)
+                }
+            });
+
+            match result {
+                Ok(result) => println!("{}", result),
+                Err(e) => {
+                    reporter.add(e.into());
+                    reporter.report(true);
+                    break;
+                }
+            }
         }
+    }
+}
+
+/// Prints an operation evaluated by `run --trace` to stderr, so that the values printed on
+/// stdout stay the same.
+fn print_trace(ast: &ast::Ast, left: &run::Value, right: &run::Value, result: &run::Value) {
+    if let ast::Ast::Op { span, op, .. } = ast {
+        eprintln!(
+            "{}:{}-{}:{} {} {} {} => {}",
+            span.start.line + 1,
+            span.start.col + 1,
+            span.end.line + 1,
+            span.end.col + 1,
+            left,
+            op,
+            right,
+            result
+        );
+    }
+}
+
+/// Prints the program back in canonical form, syntax errors are reported instead. With
+/// `normalize` it is simplified first, see `simplify::simplify` for what `unsafe_math` allows.
+fn cmd_fmt(input: &str, normalize: bool, unsafe_math: bool) {
+    let file = read_input(input);
+    let (program, reporter) = parsing::parse_str(&file.source, &file.name);
+
+    reporter.report(true);
+
+    let program = if normalize {
+        simplify::normalize(&program, run::IntMode::default(), unsafe_math)
     } else {
-        FileInfo {
-            source: input.to_string(),
-            name: "<input>".into(),
+        program
+    };
+
+    print!("{}", format::format(&program));
+}
+
+fn cmd_watch(input: &str, warn_redundant_parens: bool) {
+    use notify::{DebouncedEvent, RecursiveMode, Watcher};
+
+    let path = std::fs::canonicalize(input).unwrap_or_else(|e| {
+        eprintln!("Cannot watch `{}`: {}", input, e);
+        std::process::exit(1);
+    });
+    let dir = match path.parent() {
+        Some(dir) => dir,
+        None => {
+            eprintln!("Cannot watch `{}`: not a file", input);
+            std::process::exit(1);
         }
+    };
+    let (tx, rx) = std::sync::mpsc::channel();
+    // Successive writes within the delay are reported as a single event.
+    let mut watcher =
+        notify::watcher(tx, std::time::Duration::from_millis(200)).unwrap_or_else(|e| {
+            eprintln!("Cannot watch `{}`: {}", input, e);
+            std::process::exit(1);
+        });
+
+    // Watch the directory rather than the file itself, editors often save by replacing the file.
+    if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+        eprintln!("Cannot watch `{}`: {}", input, e);
+        std::process::exit(1);
     }
-    .intern(&files);
 
-    match parsing::parse(&reporter, file) {
-        Err(e) => {
-            reporter.add(e);
-            reporter.report(true);
+    watch_run(input, warn_redundant_parens);
+
+    for event in rx {
+        match event {
+            DebouncedEvent::Write(changed)
+            | DebouncedEvent::Create(changed)
+            | DebouncedEvent::Rename(_, changed)
+                if changed == path =>
+            {
+                watch_run(input, warn_redundant_parens)
+            }
+            _ => {}
         }
-        Ok(ast) => {
-            analysis::analyze(&reporter, &ast);
+    }
+}
 
-            let result = run::run(&ast);
+/// Runs the file in a child process, as reporting errors would otherwise end the watch.
+fn watch_run(input: &str, warn_redundant_parens: bool) {
+    use std::io::Write;
 
-            println!("{}", result);
-        }
+    print!("\x1B[2J\x1B[1;1H");
+
+    if let Err(e) = std::io::stdout().flush() {
+        fail(format!("Cannot write to stdout: {}", e));
+    }
+
+    let exe = std::env::current_exe().unwrap_or_else(|e| {
+        eprintln!("Cannot find the compiler to run `{}` with: {}", input, e);
+        std::process::exit(1);
+    });
+    let mut command = std::process::Command::new(exe);
+
+    command.arg("run").arg(input);
+
+    if warn_redundant_parens {
+        command.arg("--warn-redundant-parens");
+    }
+
+    if let Err(e) = command.status() {
+        eprintln!("Cannot run `{}`: {}", input, e);
+        std::process::exit(1);
     }
 }
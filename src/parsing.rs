@@ -1,98 +1,696 @@
 use crate::ast::*;
-use diagnostics::{FileId, Reporter, Span};
+use diagnostics::{Diagnostic, FileId, FileInfo, FileInterner, Reporter, Severity, Span, Spanned};
+use intern::Intern;
+use parser::buffer::{Entry, TokenBuffer};
 use parser::error::Result;
-use parser::literal::IntLiteral;
-use parser::parse::ParseStream;
+use parser::ident::Ident;
+use parser::literal::{CharLiteral, FloatLiteral, IntLiteral, Literal, StringLiteral};
+use parser::parse::{join, ParseStream};
+use parser::punct::Spacing;
+use parser::punctuated::Punctuated;
+use std::fmt::Write;
+
+/// Every character that may start or continue an operator or delimiter, the lexer reports
+/// anything else.
+const PUNCTUATION: &str = "+-*/%&|^~<>=!(),;";
+
+/// The mathematical signs accepted in place of the ASCII operators.
+const ALIASES: &[(char, char)] = &[('×', '*'), ('÷', '/')];
 
 parser::token![punct "+" TAdd/1];
 parser::token![punct "-" TSub/1];
 parser::token![punct "*" TMul/1];
 parser::token![punct "/" TDiv/1];
+parser::token![punct "%" TMod/1];
+parser::token![punct "**" TPow/2];
+
+parser::token![punct "&" TAnd/1];
+parser::token![punct "|" TOr/1];
+parser::token![punct "^" TXor/1];
+parser::token![punct "~" TNot/1];
+
+parser::token![punct "<" TLt/1];
+parser::token![punct ">" TGt/1];
+parser::token![punct "<=" TLe/2];
+parser::token![punct ">=" TGe/2];
+parser::token![punct "==" TEqEq/2];
+parser::token![punct "!=" TNe/2];
+
+parser::token![punct "=" TEq/1];
 
 parser::token![punct "(" TLParen/1];
 parser::token![punct ")" TRParen/1];
+parser::token![punct "," TComma/1];
+parser::token![punct ";" TSemi/1];
+
+parser::token![keyword "let" TLet];
+parser::token![keyword "in" TIn];
+parser::token![keyword "if" TIf];
+parser::token![keyword "then" TThen];
+parser::token![keyword "else" TElse];
+
+/// Lexes and parses `file`, returning the program along with the number of errors reported.
+pub fn parse(reporter: &Reporter, file: FileId) -> (Vec<Ast>, usize) {
+    let (tokens, lex_errors) = lex(reporter, file);
+    let (program, parse_errors) = parse_tokens(reporter, file, &tokens);
+
+    (program, lex_errors + parse_errors)
+}
+
+/// Parses `source` as a file called `name`, syntax errors are left in the returned reporter.
+pub fn parse_str(source: &str, name: &str) -> (Vec<Ast>, Reporter) {
+    let reporter = Reporter::default();
+    let files = FileInterner::new();
+    let file = FileInfo {
+        source: source.to_string(),
+        name: name.into(),
+    }
+    .intern(&files);
+    let (program, _) = parse(&reporter, file);
+
+    (program, reporter)
+}
+
+/// Lexes `source` and lists every token with its span, one per line, for `--dump-tokens`.
+/// Lexer errors are left to the actual parse to report.
+pub fn dump_tokens(source: &str, name: &str) -> String {
+    let reporter = Reporter::default();
+    let files = FileInterner::new();
+    let file = FileInfo {
+        source: source.to_string(),
+        name: name.into(),
+    }
+    .intern(&files);
+    let (tokens, _) = lex(&reporter, file);
+    let mut cursor = tokens.begin();
+    let mut out = String::new();
+
+    while !cursor.eof() {
+        let entry = cursor.entry();
+
+        cursor = cursor.bump();
 
-pub fn parse(reporter: &Reporter, file: FileId) -> Result<Ast> {
-    let mut lexer = parser::lexer::Lexer::new(&file.source, file, reporter);
+        let token = match entry {
+            Entry::Ident(ident) => format!("ident {}", ident.name),
+            Entry::Punct(punct) if punct.spacing == Spacing::Joint => {
+                format!("punct {:?} (joint)", punct.ch)
+            }
+            Entry::Punct(punct) => format!("punct {:?}", punct.ch),
+            Entry::Literal(literal) => format!("literal {}", literal),
+            Entry::Empty => continue,
+        };
+        let span = entry.span();
+
+        writeln!(
+            out,
+            "{}:{}-{}:{} {}",
+            span.start.line + 1,
+            span.start.col + 1,
+            span.end.line + 1,
+            span.end.col + 1,
+            token
+        )
+        .unwrap();
+    }
+
+    out
+}
+
+/// Returns the tokens along with the number of errors reported.
+pub fn lex(reporter: &Reporter, file: FileId) -> (TokenBuffer, usize) {
+    let mut lexer = parser::lexer::Lexer::new(&file.source, file, reporter)
+        .punctuation(PUNCTUATION)
+        .aliases(ALIASES);
     let tokens = lexer.run();
+
+    (tokens, lexer.errors())
+}
+
+/// Syntax errors are added to `reporter`. Where parsing can recover they are replaced by an
+/// `Ast::Error`, otherwise the expressions containing them are left out of the returned
+/// program, which comes with the number of errors. A program without any expressions is
+/// an error as well.
+pub fn parse_tokens(reporter: &Reporter, file: FileId, tokens: &TokenBuffer) -> (Vec<Ast>, usize) {
     let buffer = parser::parse::ParseBuffer::new(tokens.begin(), reporter, (), Span::empty(file));
 
-    Ast::parse_add_sub(&buffer)
+    if buffer.is_empty() {
+        reporter.add(
+            Diagnostic::new(
+                Severity::Error,
+                None,
+                "Empty program: expected an expression",
+            )
+            .label(Severity::Error, Span::empty(file), None::<String>),
+        );
+
+        return (Vec::new(), 1);
+    }
+
+    Ast::parse_program(&buffer)
+}
+
+/// Parses `source` as a single expression as if it were located at the start of `base`, so
+/// that diagnostics point into the file `base` belongs to. Anything following the
+/// expression is an error. So is a character the lexer rejects, which is reported at `base`
+/// as a whole.
+pub fn parse_fragment(source: &str, base: Span) -> Result<Ast> {
+    let reporter = Reporter::default();
+    let mut lexer = parser::lexer::Lexer::with_start(source, base.file, &reporter, base.start)
+        .punctuation(PUNCTUATION)
+        .aliases(ALIASES);
+    let tokens = lexer.run();
+
+    if lexer.errors() > 0 {
+        return Err(
+            Diagnostic::new(Severity::Error, None, "Invalid character in fragment").label(
+                Severity::Error,
+                base,
+                None::<String>,
+            ),
+        );
+    }
+
+    let start = Span {
+        end: base.start,
+        ..base
+    };
+    let buffer = parser::parse::ParseBuffer::new(tokens.begin(), &reporter, (), start);
+    let ast = Ast::parse_expr(&buffer)?;
+
+    if !buffer.is_empty() {
+        return buffer.error("unexpected trailing tokens", None);
+    }
+
+    // The diagnostics of recovered errors are only in the local reporter.
+    if let Some(span) = first_error(&ast) {
+        return buffer.error_at("Expected an operand after the operator", span, None);
+    }
+
+    Ok(ast)
+}
+
+/// The number of `Ast::Error`s in `ast`.
+fn count_errors(ast: &Ast) -> usize {
+    let mut spans = Vec::new();
+
+    error_spans(ast, &mut spans);
+    spans.len()
+}
+
+/// The span of the first `Ast::Error` in `ast`, if there is one.
+fn first_error(ast: &Ast) -> Option<Span> {
+    let mut spans = Vec::new();
+
+    error_spans(ast, &mut spans);
+    spans.first().copied()
+}
+
+/// Adds the span of every `Ast::Error` in `ast` to `spans`, in source order.
+fn error_spans(ast: &Ast, spans: &mut Vec<Span>) {
+    crate::grow_stack(|| match ast {
+        Ast::Error { span } => spans.push(*span),
+        Ast::Int { .. } | Ast::Float { .. } | Ast::Str { .. } | Ast::Var { .. } => {}
+        Ast::Op { left, right, .. } | Ast::Cmp { left, right, .. } => {
+            error_spans(left, spans);
+            error_spans(right, spans);
+        }
+        Ast::Neg { expr, .. } | Ast::Not { expr, .. } | Ast::Group { expr, .. } => {
+            error_spans(expr, spans)
+        }
+        Ast::Let { value, body, .. } => {
+            error_spans(value, spans);
+            error_spans(body, spans);
+        }
+        Ast::If {
+            cond, then, else_, ..
+        } => {
+            error_spans(cond, spans);
+            error_spans(then, spans);
+            error_spans(else_, spans);
+        }
+        Ast::Call { args, .. } => {
+            for arg in args {
+                error_spans(arg, spans);
+            }
+        }
+    })
 }
 
 impl Ast {
+    /// A program is a list of expressions separated by semicolons, the last of which may be
+    /// followed by one as well. Anything else following an expression is an error.
+    fn parse_program(input: ParseStream) -> (Vec<Self>, usize) {
+        let mut program = Vec::new();
+        let mut errors = 0;
+
+        while !input.is_empty() {
+            match Self::parse_statement(input) {
+                Ok((ast, recovered)) => {
+                    errors += recovered;
+                    program.push(ast);
+                }
+                Err(e) => {
+                    input.reporter.add(e);
+                    errors += 1;
+                    Self::synchronize(input);
+                }
+            }
+        }
+
+        (program, errors)
+    }
+
+    /// An expression followed by `;` or the end of the input, along with the number of
+    /// errors recovered from within it. Operands missing an operator in between, as in
+    /// `2 3`, are reported and the whole expression is replaced by an `Ast::Error`.
+    fn parse_statement(input: ParseStream) -> Result<(Self, usize)> {
+        let start = input.span();
+        let mut ast = Self::parse_expr(input)?;
+        let mut errors = count_errors(&ast);
+
+        // Every expression parsed here consumes at least the token that starts it.
+        while Self::peek_operand(input) {
+            input.reporter.add(
+                Diagnostic::new(Severity::Error, None, "Expected an operator").label(
+                    Severity::Error,
+                    input.span(),
+                    None::<String>,
+                ),
+            );
+
+            errors += 1 + count_errors(&Self::parse_expr(input)?);
+            ast = Self::Error {
+                span: input.span_from(start),
+            };
+        }
+
+        if input.is_empty() || input.parse::<TSemi>().is_ok() {
+            Ok((ast, errors))
+        } else {
+            input.error("unexpected trailing tokens", None)
+        }
+    }
+
+    /// Skips past the next `;` so parsing can resume at the expression following an error.
+    fn synchronize(input: ParseStream) {
+        while !input.is_empty() && input.parse::<TSemi>().is_err() {
+            input.bump();
+        }
+    }
+
+    /// The bitwise operators bind loosest, as in C. See `Op::precedence`.
+    fn parse_expr(input: ParseStream) -> Result<Self> {
+        Self::parse_bit_or(input)
+    }
+
+    fn parse_bit_or(input: ParseStream) -> Result<Self> {
+        let start = input.span();
+        let mut result = Self::parse_bit_xor(input)?;
+
+        while !input.is_empty() && input.peek::<TOr>() {
+            let op_span = input.parse::<TOr>()?.span;
+            let right = Self::parse_operand(input, op_span, Self::parse_bit_xor)?;
+
+            result = Self::Op {
+                span: input.span_from(start),
+                op: Op::BitOr,
+                op_span,
+                left: Box::new(result),
+                right: Box::new(right),
+            };
+        }
+
+        Ok(result)
+    }
+
+    fn parse_bit_xor(input: ParseStream) -> Result<Self> {
+        let start = input.span();
+        let mut result = Self::parse_bit_and(input)?;
+
+        while !input.is_empty() && input.peek::<TXor>() {
+            let op_span = input.parse::<TXor>()?.span;
+            let right = Self::parse_operand(input, op_span, Self::parse_bit_and)?;
+
+            result = Self::Op {
+                span: input.span_from(start),
+                op: Op::BitXor,
+                op_span,
+                left: Box::new(result),
+                right: Box::new(right),
+            };
+        }
+
+        Ok(result)
+    }
+
+    fn parse_bit_and(input: ParseStream) -> Result<Self> {
+        let start = input.span();
+        let mut result = Self::parse_cmp(input)?;
+
+        while !input.is_empty() && input.peek::<TAnd>() {
+            let op_span = input.parse::<TAnd>()?.span;
+            let right = Self::parse_operand(input, op_span, Self::parse_cmp)?;
+
+            result = Self::Op {
+                span: input.span_from(start),
+                op: Op::BitAnd,
+                op_span,
+                left: Box::new(result),
+                right: Box::new(right),
+            };
+        }
+
+        Ok(result)
+    }
+
+    /// The two-character operators are tried first as `<` would otherwise match the start
+    /// of `<=`.
+    fn parse_cmp(input: ParseStream) -> Result<Self> {
+        let start = input.span();
+        let mut result = Self::parse_add_sub(input)?;
+
+        while !input.is_empty() {
+            let (op, op_span) = if input.peek::<TLe>() {
+                (CmpOp::Le, input.parse::<TLe>()?.span)
+            } else if input.peek::<TGe>() {
+                (CmpOp::Ge, input.parse::<TGe>()?.span)
+            } else if input.peek::<TEqEq>() {
+                (CmpOp::Eq, input.parse::<TEqEq>()?.span)
+            } else if input.peek::<TNe>() {
+                (CmpOp::Ne, input.parse::<TNe>()?.span)
+            } else if input.peek::<TLt>() {
+                (CmpOp::Lt, input.parse::<TLt>()?.span)
+            } else if input.peek::<TGt>() {
+                (CmpOp::Gt, input.parse::<TGt>()?.span)
+            } else {
+                break;
+            };
+
+            let right = Self::parse_operand(input, op_span, Self::parse_add_sub)?;
+
+            result = Self::Cmp {
+                span: input.span_from(start),
+                op,
+                left: Box::new(result),
+                right: Box::new(right),
+            };
+        }
+
+        Ok(result)
+    }
+
     fn parse_add_sub(input: ParseStream) -> Result<Self> {
         let start = input.span();
         let mut result = Self::parse_mul_div(input)?;
 
         while !input.is_empty() && (input.peek::<TAdd>() || input.peek::<TSub>()) {
-            if let Ok(_) = input.parse::<TAdd>() {
-                let right = Self::parse_mul_div(input)?;
-
-                result = Self::Op {
-                    span: start.to(input.prev_span()),
-                    op: Op::Add,
-                    left: Box::new(result),
-                    right: Box::new(right),
-                };
+            let (op, op_span) = if let Ok(add) = input.parse::<TAdd>() {
+                (Op::Add, add.span)
             } else {
-                input.parse::<TSub>()?;
+                (Op::Sub, input.parse::<TSub>()?.span)
+            };
 
-                let right = Self::parse_int(input)?;
+            let right = Self::parse_operand(input, op_span, Self::parse_mul_div)?;
 
-                result = Self::Op {
-                    span: start.to(input.prev_span()),
-                    op: Op::Sub,
-                    left: Box::new(result),
-                    right: Box::new(right),
-                };
-            }
+            result = Self::Op {
+                span: input.span_from(start),
+                op,
+                op_span,
+                left: Box::new(result),
+                right: Box::new(right),
+            };
         }
 
         Ok(result)
     }
 
+    /// A `(` directly following an integer literal or a parenthesized operand multiplies
+    /// it, with the same precedence as `*`: `6 / 2(3)` is `6 / 2 * 3` and `(1 + 1)(2 + 2)`
+    /// is `(1 + 1) * (2 + 2)`. Being an operand itself, the factor binds looser than `**`, so
+    /// `2(3) ** 2` is `2 * (3 ** 2)`.
     fn parse_mul_div(input: ParseStream) -> Result<Self> {
         let start = input.span();
-        let mut result = Self::parse_int(input)?;
-
-        while !input.is_empty() && (input.peek::<TMul>() || input.peek::<TDiv>()) {
-            if let Ok(_) = input.parse::<TMul>() {
-                let right = Self::parse_int(input)?;
-
-                result = Self::Op {
-                    span: start.to(input.prev_span()),
-                    op: Op::Mul,
-                    left: Box::new(result),
-                    right: Box::new(right),
-                };
+        let mut result = Self::parse_unary(input)?;
+        // Whether the operand parsed last, not all of `result`, can be followed by a factor.
+        let mut last = Self::is_implicit_factor(&result);
+
+        while !input.is_empty()
+            && (input.peek::<TMul>()
+                || input.peek::<TDiv>()
+                || input.peek::<TMod>()
+                || last && input.peek::<TLParen>())
+        {
+            let (op, op_span) = if let Ok(mul) = input.parse::<TMul>() {
+                (Op::Mul, mul.span)
+            } else if let Ok(div) = input.parse::<TDiv>() {
+                (Op::Div, div.span)
+            } else if let Ok(rem) = input.parse::<TMod>() {
+                (Op::Mod, rem.span)
             } else {
-                input.parse::<TDiv>()?;
+                // There is no operator, so the span is the point between the operands.
+                let next = input.span();
 
-                let right = Self::parse_int(input)?;
+                (
+                    Op::Mul,
+                    Span {
+                        end: next.start,
+                        ..next
+                    },
+                )
+            };
 
-                result = Self::Op {
-                    span: start.to(input.prev_span()),
-                    op: Op::Div,
-                    left: Box::new(result),
-                    right: Box::new(right),
-                };
-            }
+            let right = Self::parse_operand(input, op_span, Self::parse_unary)?;
+
+            last = Self::is_implicit_factor(&right);
+            result = Self::Op {
+                span: input.span_from(start),
+                op,
+                op_span,
+                left: Box::new(result),
+                right: Box::new(right),
+            };
         }
 
         Ok(result)
     }
 
+    /// Whether a `(` following `operand` multiplies it, see `parse_mul_div`. That is when
+    /// its last primary operand is an integer literal or a group, as in `-2(3)` or
+    /// `2 ** (3)(4)`. Any other operand followed by another, like `1 2` or `(1) 2`, is still
+    /// an error, and `x(2)` is a call.
+    fn is_implicit_factor(operand: &Self) -> bool {
+        match operand {
+            Self::Int { .. } | Self::Group { .. } => true,
+            Self::Neg { expr, .. } | Self::Not { expr, .. } => Self::is_implicit_factor(expr),
+            Self::Op {
+                op: Op::Pow, right, ..
+            } => Self::is_implicit_factor(right),
+            _ => false,
+        }
+    }
+
+    /// Parses the operand following the operator at `op_span` with `parse`. If there is
+    /// none, as in `2 +`, the error is reported and an `Ast::Error` takes its place, so the
+    /// operation stays in the tree. Nothing is consumed then, the caller moves on to the
+    /// token that couldn't start an operand.
+    fn parse_operand(
+        input: ParseStream,
+        op_span: Span,
+        parse: fn(ParseStream) -> Result<Self>,
+    ) -> Result<Self> {
+        if Self::peek_operand(input) {
+            return parse(input);
+        }
+
+        input.reporter.add(
+            Diagnostic::new(
+                Severity::Error,
+                None,
+                "Expected an operand after the operator",
+            )
+            .label(Severity::Error, op_span, None::<String>),
+        );
+
+        Ok(Self::Error {
+            span: Span {
+                start: op_span.end,
+                ..op_span
+            },
+        })
+    }
+
+    /// Whether the next token can start an operand. Keywords other than `let` and `if`
+    /// can't, even though they are identifiers.
+    fn peek_operand(input: ParseStream) -> bool {
+        let keyword = input.peek::<TIn>() || input.peek::<TThen>() || input.peek::<TElse>();
+
+        !input.is_empty()
+            && !keyword
+            && (input.peek::<Literal>()
+                || input.peek::<Ident>()
+                || input.peek::<TLParen>()
+                || input.peek::<TSub>()
+                || input.peek::<TNot>())
+    }
+
+    /// Negation and complement bind looser than `**`, so `-2 ** 2` is `-(2 ** 2)`.
+    fn parse_unary(input: ParseStream) -> Result<Self> {
+        crate::grow_stack(|| {
+            if let Ok(minus) = input.parse::<TSub>() {
+                let expr = Self::parse_operand(input, minus.span, Self::parse_unary)?;
+
+                Ok(Self::Neg {
+                    span: input.span_from(minus.span),
+                    expr: Box::new(expr),
+                })
+            } else if let Ok(not) = input.parse::<TNot>() {
+                let expr = Self::parse_operand(input, not.span, Self::parse_unary)?;
+
+                Ok(Self::Not {
+                    span: input.span_from(not.span),
+                    expr: Box::new(expr),
+                })
+            } else {
+                Self::parse_pow(input)
+            }
+        })
+    }
+
+    /// `**` is right-associative, so the exponent recurses into this level again.
+    fn parse_pow(input: ParseStream) -> Result<Self> {
+        let start = input.span();
+        let base = Self::parse_int(input)?;
+
+        if !input.is_empty() && input.peek::<TPow>() {
+            let op_span = input.parse::<TPow>()?.span;
+            let exponent = Self::parse_operand(input, op_span, Self::parse_unary)?;
+
+            Ok(Self::Op {
+                span: input.span_from(start),
+                op: Op::Pow,
+                op_span,
+                left: Box::new(base),
+                right: Box::new(exponent),
+            })
+        } else {
+            Ok(base)
+        }
+    }
+
+    /// The body of a `let` extends as far as possible, so `let x = 1 in x + 1` binds `x`
+    /// in the whole of `x + 1`.
+    fn parse_let(input: ParseStream) -> Result<Self> {
+        let start = input.parse::<TLet>()?.span;
+        let name = input.parse::<Ident>()?;
+
+        input.parse::<TEq>()?;
+
+        let value = Self::parse_expr(input)?;
+
+        input.parse::<TIn>()?;
+
+        let body = Self::parse_expr(input)?;
+
+        Ok(Self::Let {
+            span: input.span_from(start),
+            name: name.name,
+            value: Box::new(value),
+            body: Box::new(body),
+        })
+    }
+
+    /// The `else` branch is required and, like the body of a `let`, extends as far as
+    /// possible.
+    fn parse_if(input: ParseStream) -> Result<Self> {
+        let start = input.parse::<TIf>()?.span;
+        let cond = Self::parse_expr(input)?;
+
+        input.parse::<TThen>()?;
+
+        let then = Self::parse_expr(input)?;
+
+        input.parse::<TElse>()?;
+
+        let else_ = Self::parse_expr(input)?;
+
+        Ok(Self::If {
+            span: input.span_from(start),
+            cond: Box::new(cond),
+            then: Box::new(then),
+            else_: Box::new(else_),
+        })
+    }
+
+    fn parse_call(input: ParseStream, name: Ident) -> Result<Self> {
+        input.parse::<TLParen>()?;
+
+        let args = if input.peek::<TRParen>() {
+            Vec::new()
+        } else {
+            Punctuated::<_, TComma>::parse_separated_with(input, Self::parse_expr)?.into_values()
+        };
+
+        input.parse::<TRParen>()?;
+
+        Ok(Self::Call {
+            span: input.span_from(name.span),
+            name: name.name,
+            name_span: name.span,
+            args,
+        })
+    }
+
     fn parse_int(input: ParseStream) -> Result<Self> {
-        if let Ok(lparen) = input.parse::<TLParen>() {
-            let sub = Self::parse_add_sub(input)?;
+        if input.peek::<TLet>() {
+            Self::parse_let(input)
+        } else if input.peek::<TIf>() {
+            Self::parse_if(input)
+        } else if input.peek::<TIn>() || input.peek::<TThen>() || input.peek::<TElse>() {
+            input.error("expected an expression", None)
+        } else if let Ok(name) = input.parse::<Ident>() {
+            if input.peek::<TLParen>() {
+                Self::parse_call(input, name)
+            } else {
+                Ok(Self::Var {
+                    span: name.span,
+                    name: name.name,
+                })
+            }
+        } else if let Ok(lparen) = input.parse::<TLParen>() {
+            if input.peek::<TRParen>() {
+                let rparen = input.parse::<TRParen>()?;
+
+                return input.error_at(
+                    "Empty parentheses are not allowed",
+                    join(lparen.span, rparen.span),
+                    None,
+                );
+            }
+
+            let sub = Self::parse_expr(input)?;
 
             input.parse::<TRParen>()?;
 
             Ok(Self::Group {
-                span: lparen.span.to(input.prev_span()),
+                span: input.span_from(lparen.span),
                 expr: Box::new(sub),
             })
+        } else if let Ok(lit) = input.parse::<CharLiteral>() {
+            // Characters are integers, their Unicode scalar value.
+            Ok(Self::Int {
+                span: lit.span,
+                val: lit.ch as u64,
+            })
+        } else if let Ok(lit) = input.parse::<StringLiteral>() {
+            Ok(Self::Str {
+                span: lit.span,
+                val: lit.text,
+            })
+        } else if let Ok(lit) = input.parse::<FloatLiteral>() {
+            Ok(Self::Float {
+                span: lit.span,
+                val: f64::from_bits(lit.float),
+            })
         } else {
             let lit = input.parse::<IntLiteral>()?;
 
@@ -103,3 +701,306 @@ impl Ast {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::Constants;
+    use crate::parse_test;
+    use crate::run::{self, IntMode, Value};
+    use diagnostics::Position;
+
+    /// Parses `source`, returning the program along with the number of errors.
+    fn parse_errors(source: &str) -> (Vec<Ast>, usize) {
+        let files = FileInterner::new();
+        let file = FileInfo {
+            source: source.to_string(),
+            name: "<test>".into(),
+        }
+        .intern(&files);
+
+        parse(&Reporter::default(), file)
+    }
+
+    fn parse_one(source: &str) -> Ast {
+        match parse_test(source) {
+            Some(mut program) if program.len() == 1 => program.remove(0),
+            _ => panic!("`{}` isn't a single expression", source),
+        }
+    }
+
+    #[test]
+    fn adjacent_operands_are_errors() {
+        assert!(parse_test("1 2").is_none());
+        assert!(parse_test("(1) 2").is_none());
+        assert!(parse_test("1 2; 3").is_none());
+    }
+
+    #[test]
+    fn unknown_punctuation_is_error() {
+        // The lexer skips the character, so these would parse without its error.
+        assert!(parse_test("1 @").is_none());
+        assert!(parse_test("1 + 2 $").is_none());
+        assert!(parse_test("#1").is_none());
+    }
+
+    #[test]
+    fn subtraction_binds_looser_than_multiplication() {
+        let ast = parse_one("10 - 2 * 3");
+
+        match &ast {
+            Ast::Op {
+                op: Op::Sub,
+                left,
+                right,
+                ..
+            } => {
+                assert!(matches!(**left, Ast::Int { val: 10, .. }));
+                assert!(matches!(**right, Ast::Op { op: Op::Mul, .. }));
+            }
+            ast => panic!("parsed to {}", ast),
+        }
+
+        let mode = IntMode::default();
+        let constants = Constants::find(std::slice::from_ref(&ast), mode);
+
+        assert_eq!(run::run(&ast, &constants, mode), Ok(Value::Int(4)));
+    }
+
+    #[test]
+    fn empty_program_is_an_error() {
+        for source in &["", "  \n", "// nothing\n", "/* nothing */"] {
+            let (program, errors) = parse_errors(source);
+
+            assert!(program.is_empty(), "{:?}", source);
+            assert_eq!(errors, 1, "{:?}", source);
+        }
+
+        assert!(parse_test("").is_none());
+    }
+
+    #[test]
+    fn empty_parentheses_are_one_error() {
+        // Rather than a missing expression followed by a stray `)`.
+        for source in &["()", "( )", "1 + ()", "2 * (())"] {
+            let (program, errors) = parse_errors(source);
+
+            assert!(program.is_empty(), "{}", source);
+            assert_eq!(errors, 1, "{}", source);
+        }
+
+        // The statement is skipped, the ones following it still parse.
+        let (program, errors) = parse_errors("(); 1");
+
+        assert_eq!(errors, 1);
+        assert!(matches!(program[..], [Ast::Int { val: 1, .. }]));
+        assert!(matches!(parse_one("f()"), Ast::Call { .. }));
+    }
+
+    #[test]
+    fn operator_spans_cover_the_operator() {
+        let op_span = |source: &str| match parse_one(source) {
+            Ast::Op { op_span, .. } => (
+                (op_span.start.offset, op_span.end.offset),
+                (op_span.start.col, op_span.end.col),
+            ),
+            ast => panic!("parsed to {}", ast),
+        };
+
+        assert_eq!(op_span("1+2"), ((1, 2), (1, 2)));
+        assert_eq!(op_span("1 + 2"), ((2, 3), (2, 3)));
+        assert_eq!(op_span("1**2"), ((1, 3), (1, 3)));
+    }
+
+    #[test]
+    fn tokens_snapshot() {
+        let files = FileInterner::new();
+        let file = FileInfo {
+            source: "let x = 0x1F in x ** 2 × (1.5 - 'a') <= \"s\" // done".to_string(),
+            name: "<test>".into(),
+        }
+        .intern(&files);
+        let (tokens, errors) = lex(&Reporter::default(), file);
+
+        assert_eq!(errors, 0);
+        assert_eq!(
+            parser::snapshot::tokens(&tokens),
+            "IDENT(let) IDENT(x) '=' INT(31) IDENT(in) IDENT(x) '**' INT(2) '*' '(' FLOAT(1.5) \
+             '-' CHAR('a') ')' '<=' STR(\"s\")"
+        );
+    }
+
+    #[test]
+    fn aliases_parse_as_operators() {
+        match parse_one("2 × 3") {
+            Ast::Op {
+                op: Op::Mul,
+                op_span,
+                ..
+            } => assert_eq!((op_span.start.offset, op_span.end.offset), (2, 4)),
+            ast => panic!("parsed to {}", ast),
+        }
+
+        assert!(matches!(parse_one("6 ÷ 2"), Ast::Op { op: Op::Div, .. }));
+    }
+
+    #[test]
+    fn parse_fragment_rebases_spans() {
+        let base = Span {
+            start: Position {
+                line: 2,
+                col: 4,
+                offset: 10,
+            },
+            ..Span::default()
+        };
+
+        match parse_fragment("1 + 2", base) {
+            Ok(ast) => {
+                assert_eq!(ast.span().start.offset, 10);
+                assert_eq!(ast.span().end.offset, 15);
+            }
+            Err(_) => panic!("`1 + 2` didn't parse"),
+        }
+    }
+
+    #[test]
+    fn parse_fragment_rejects_trailing_tokens() {
+        assert!(parse_fragment("1 )", Span::default()).is_err());
+        assert!(parse_fragment("1 2", Span::default()).is_err());
+        assert!(parse_fragment("1 $", Span::default()).is_err());
+    }
+
+    #[test]
+    fn deep_nesting_parses() {
+        let depth = 50_000;
+        let source = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+
+        assert!(parse_test(&source).is_some());
+    }
+
+    #[test]
+    fn missing_operands_are_errors() {
+        let (program, _) = parse_str("2 +; -; 1 * (3 -)", "<test>");
+
+        assert_eq!(program.len(), 3);
+
+        match &program[0] {
+            Ast::Op {
+                op: Op::Add, right, ..
+            } => assert!(matches!(**right, Ast::Error { .. })),
+            ast => panic!("parsed to {}", ast),
+        }
+
+        match &program[1] {
+            Ast::Neg { expr, .. } => assert!(matches!(**expr, Ast::Error { .. })),
+            ast => panic!("parsed to {}", ast),
+        }
+
+        assert_eq!(program[2].to_string(), "1 * (3 - <error>)");
+        assert!(parse_test("2 +").is_none());
+        assert!(parse_fragment("1 +", Span::default()).is_err());
+    }
+
+    #[test]
+    fn missing_operators_are_errors() {
+        let (program, _) = parse_str("2 3 4; 5", "<test>");
+
+        assert_eq!(program.len(), 2);
+
+        match &program[0] {
+            Ast::Error { span } => assert_eq!((span.start.offset, span.end.offset), (0, 5)),
+            ast => panic!("parsed to {}", ast),
+        }
+
+        assert!(matches!(program[1], Ast::Int { val: 5, .. }));
+    }
+
+    #[test]
+    fn stripping_groups_normalizes() {
+        let grouped = parse_one("((1+2))").strip_groups();
+        let plain = parse_one("1+2").strip_groups();
+
+        assert!(matches!(grouped, Ast::Op { op: Op::Add, .. }));
+        assert_eq!(grouped.to_string(), plain.to_string());
+
+        let nested = parse_one("(1) * ((2 + (3)))").strip_groups();
+
+        assert_eq!(nested.to_string(), "1 * 2 + 3");
+    }
+
+    #[test]
+    fn implicit_mul_after_int() {
+        assert!(matches!(parse_one("2(3 + 4)"), Ast::Op { op: Op::Mul, .. }));
+
+        match &parse_one("6 / 2(3)") {
+            Ast::Op {
+                op: Op::Mul, left, ..
+            } => assert!(matches!(**left, Ast::Op { op: Op::Div, .. })),
+            ast => panic!("parsed as {}", ast),
+        }
+
+        match &parse_one("2(3) ** 2") {
+            Ast::Op {
+                op: Op::Mul, right, ..
+            } => assert!(matches!(**right, Ast::Op { op: Op::Pow, .. })),
+            ast => panic!("parsed as {}", ast),
+        }
+    }
+
+    #[test]
+    fn implicit_mul_after_unary_and_pow() {
+        match &parse_one("-2(3)") {
+            Ast::Op {
+                op: Op::Mul, left, ..
+            } => assert!(matches!(**left, Ast::Neg { .. })),
+            ast => panic!("parsed as {}", ast),
+        }
+
+        match &parse_one("2 ** 3(4)") {
+            Ast::Op {
+                op: Op::Mul, left, ..
+            } => assert!(matches!(**left, Ast::Op { op: Op::Pow, .. })),
+            ast => panic!("parsed as {}", ast),
+        }
+    }
+
+    #[test]
+    fn implicit_mul_after_group() {
+        assert!(matches!(parse_one("(2)(3)"), Ast::Op { op: Op::Mul, .. }));
+
+        match &parse_one("(1 + 1)(2 + 2)") {
+            Ast::Op {
+                op: Op::Mul,
+                left,
+                right,
+                ..
+            } => {
+                assert!(matches!(**left, Ast::Group { .. }));
+                assert!(matches!(**right, Ast::Group { .. }));
+            }
+            ast => panic!("parsed as {}", ast),
+        }
+
+        match &parse_one("1 + 6 / (2)(3)") {
+            Ast::Op {
+                op: Op::Add, right, ..
+            } => assert!(matches!(**right, Ast::Op { op: Op::Mul, .. })),
+            ast => panic!("parsed as {}", ast),
+        }
+    }
+
+    #[test]
+    fn implicit_mul_leaves_calls() {
+        assert!(matches!(parse_one("x(2)"), Ast::Call { .. }));
+        assert!(matches!(parse_one("2 * x(2)"), Ast::Op { op: Op::Mul, .. }));
+    }
+
+    #[test]
+    fn implicit_mul_needs_int_or_group() {
+        assert!(parse_test("x 2").is_none());
+        assert!(parse_test("2 x").is_none());
+        assert!(parse_test("2.5(3)").is_none());
+        assert!(parse_test("x(2)(3)").is_none());
+    }
+}
@@ -0,0 +1,81 @@
+#[cfg(feature = "profile-alloc")]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "profile-alloc")]
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Wraps the system allocator, counting every allocation made through it.
+#[cfg(feature = "profile-alloc")]
+struct Counting;
+
+#[cfg(feature = "profile-alloc")]
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        COUNT.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        COUNT.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Options {
+    /// Report the allocations made by every phase.
+    pub alloc: bool,
+    /// Report the wall-clock time taken by every phase.
+    pub time: bool,
+}
+
+pub fn available() -> bool {
+    cfg!(feature = "profile-alloc")
+}
+
+/// Runs `f`, reporting what `options` asks for to stderr.
+pub fn measure<T>(options: Options, phase: &str, f: impl FnOnce() -> T) -> T {
+    if !options.alloc && !options.time {
+        return f();
+    }
+
+    let count = COUNT.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    let mut line = format!("{:<8}", phase);
+
+    if options.time {
+        write!(line, " {:>10.3} ms", elapsed.as_secs_f64() * 1000.0).unwrap();
+    }
+
+    if options.alloc {
+        write!(
+            line,
+            " {:>8} allocations {:>10} bytes",
+            COUNT.load(Ordering::Relaxed) - count,
+            BYTES.load(Ordering::Relaxed) - bytes,
+        )
+        .unwrap();
+    }
+
+    eprintln!("{}", line);
+
+    result
+}
@@ -1,21 +1,779 @@
 use crate::ast::*;
+use crate::constants::Constants;
+use diagnostics::{Diagnostic, Severity, Span, Spanned};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
 
-pub fn run(ast: &Ast) -> u64 {
-    match ast {
-        Ast::Int { val, .. } => *val,
-        Ast::Op {
-            op, left, right, ..
-        } => {
-            let left = run(left);
-            let right = run(right);
-
-            match op {
-                Op::Add => left + right,
-                Op::Sub => left - right,
-                Op::Mul => left * right,
-                Op::Div => left / right,
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(u64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+impl Value {
+    /// The value as a float, promoting integers. Booleans and strings aren't numbers.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Self::Int(val) => Some(*val as f64),
+            Self::Float(val) => Some(*val),
+            Self::Bool(_) | Self::Str(_) => None,
+        }
+    }
+
+    /// Whether `self` and `other` are equal, allowing floats to differ by `epsilon` relative
+    /// to the larger of them, or absolutely below 1. Everything else compares exactly, and
+    /// an integer never equals a float. Unlike with `==`, NaN equals NaN, so that results
+    /// can be compared in tests.
+    pub fn approx_eq(&self, other: &Value, epsilon: f64) -> bool {
+        match (self, other) {
+            (Self::Float(a), Self::Float(b)) if a.is_nan() || b.is_nan() => {
+                a.is_nan() && b.is_nan()
+            }
+            // Infinities only equal themselves, their difference would be NaN.
+            (Self::Float(a), Self::Float(b)) if a.is_infinite() || b.is_infinite() => a == b,
+            (Self::Float(a), Self::Float(b)) => {
+                (a - b).abs() <= epsilon * a.abs().max(b.abs()).max(1.0)
             }
+            (a, b) => a == b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub span: Span,
+    pub kind: RuntimeErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuntimeErrorKind {
+    Overflow(Op),
+    Underflow,
+    DivisionByZero,
+    TypeMismatch,
+    NotANumber,
+    NotAnInteger,
+    NotABoolean,
+    StepLimitExceeded,
+    /// `int` of a float outside of the integer range, NaN included.
+    OutOfRange,
+    /// A call to a function that doesn't exist or with arguments it doesn't take, which
+    /// analysis rejects before a program runs.
+    BadCall,
+}
+
+/// How integers behave, shared by evaluation, constant folding and code generation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntMode {
+    /// The number of bits in an integer, one of 8, 16, 32 or 64. Complement always wraps
+    /// around.
+    pub width: u32,
+    pub overflow: Overflow,
+}
+
+/// What happens to arithmetic with a result that doesn't fit, like Rust's debug and release
+/// builds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Fail with an error.
+    Trap,
+    /// Keep the low bits of the result.
+    Wrap,
+}
+
+impl Default for IntMode {
+    fn default() -> IntMode {
+        IntMode {
+            width: 64,
+            overflow: Overflow::Trap,
+        }
+    }
+}
+
+impl IntMode {
+    /// The largest integer that fits.
+    pub fn max(self) -> u64 {
+        if self.width >= 64 {
+            u64::MAX
+        } else {
+            (1 << self.width) - 1
+        }
+    }
+
+    /// Truncates `val` to the width.
+    pub fn mask(self, val: u64) -> u64 {
+        val & self.max()
+    }
+}
+
+/// Evaluates `ast`. Its constant subexpressions are looked up in `constants`, as found by
+/// [`Constants::find`].
+pub fn run(ast: &Ast, constants: &Constants, mode: IntMode) -> Result<Value, RuntimeError> {
+    eval(
+        ast,
+        &mut HashMap::new(),
+        constants,
+        &mut usize::max_value(),
+        &mut |_, _, _, _| {},
+        mode,
+    )
+}
+
+/// Runs every statement of `program` in order, stopping at the first error, and pairs
+/// each result with the span of its statement.
+pub fn run_top_level(
+    program: &[Ast],
+    constants: &Constants,
+    mode: IntMode,
+) -> Result<Vec<(Span, Value)>, RuntimeError> {
+    program
+        .iter()
+        .map(|ast| Ok((ast.span(), run(ast, constants, mode)?)))
+        .collect()
+}
+
+/// Like [`run`], but fails once more than `limit` nodes have been evaluated, so that
+/// untrusted programs can't run for arbitrarily long. Constant subexpressions are evaluated
+/// as well instead of being looked up, as finding them isn't bounded by the limit.
+pub fn run_with_limit(ast: &Ast, mode: IntMode, limit: usize) -> Result<Value, RuntimeError> {
+    let mut steps = limit;
+
+    eval(
+        ast,
+        &mut HashMap::new(),
+        &Constants::new(),
+        &mut steps,
+        &mut |_, _, _, _| {},
+        mode,
+    )
+}
+
+/// Like [`run`], but calls `trace` with every operation that succeeds, its operands and its
+/// result, innermost first. Constant operations are evaluated as well instead of being
+/// looked up, so that none are left out.
+pub fn run_traced(ast: &Ast, mode: IntMode, trace: &mut Trace) -> Result<Value, RuntimeError> {
+    eval(
+        ast,
+        &mut HashMap::new(),
+        &Constants::new(),
+        &mut usize::max_value(),
+        trace,
+        mode,
+    )
+}
+
+/// Called with an `Ast::Op`, its operands and its result.
+pub type Trace<'t> = dyn FnMut(&Ast, &Value, &Value, &Value) + 't;
+
+/// Constant subexpressions are looked up in `constants` rather than evaluated again. Each
+/// node evaluated takes one of the remaining `steps`.
+fn eval(
+    ast: &Ast,
+    env: &mut HashMap<String, Value>,
+    constants: &Constants,
+    steps: &mut usize,
+    trace: &mut Trace,
+    mode: IntMode,
+) -> Result<Value, RuntimeError> {
+    crate::grow_stack(|| {
+        if *steps == 0 {
+            return Err(RuntimeError {
+                span: ast.span(),
+                kind: RuntimeErrorKind::StepLimitExceeded,
+            });
+        }
+
+        *steps -= 1;
+
+        if let Some(val) = constants.get(ast) {
+            return Ok(Value::Int(val));
         }
-        Ast::Group { expr, .. } => run(expr),
+
+        match ast {
+            Ast::Int { val, .. } => Ok(Value::Int(mode.mask(*val))),
+            Ast::Float { val, .. } => Ok(Value::Float(*val)),
+            Ast::Str { val, .. } => Ok(Value::Str(val.clone())),
+            Ast::Op {
+                span,
+                op,
+                op_span,
+                left,
+                right,
+            } => {
+                let operands = (
+                    eval(left, env, constants, steps, trace, mode)?,
+                    eval(right, env, constants, steps, trace, mode)?,
+                );
+                let result = match &operands {
+                    (Value::Int(left), Value::Int(right)) => {
+                        apply_int(*op, *left, *right, mode).map(Value::Int)
+                    }
+                    (Value::Str(left), Value::Str(right)) if *op == Op::Add => {
+                        Ok(Value::Str(format!("{}{}", left, right)))
+                    }
+                    // An integer mixed with a float is promoted.
+                    (left, right) => match (left.as_float(), right.as_float()) {
+                        (Some(left), Some(right)) => {
+                            apply_float(*op, left, right).map(Value::Float)
+                        }
+                        _ => Err(RuntimeErrorKind::NotANumber),
+                    },
+                };
+
+                if let Ok(result) = &result {
+                    trace(ast, &operands.0, &operands.1, result);
+                }
+
+                // Failed arithmetic points at the operator, a non-number at the whole operation.
+                result.map_err(|kind| RuntimeError {
+                    span: match kind {
+                        RuntimeErrorKind::NotANumber => *span,
+                        _ => *op_span,
+                    },
+                    kind,
+                })
+            }
+            Ast::Cmp {
+                span,
+                op,
+                left,
+                right,
+            } => match (
+                eval(left, env, constants, steps, trace, mode)?,
+                eval(right, env, constants, steps, trace, mode)?,
+            ) {
+                (Value::Int(left), Value::Int(right)) => Ok(Value::Bool(compare(*op, left, right))),
+                (Value::Bool(left), Value::Bool(right)) => {
+                    Ok(Value::Bool(compare(*op, left, right)))
+                }
+                (Value::Str(left), Value::Str(right)) => Ok(Value::Bool(compare(*op, left, right))),
+                (left, right) => match (left.as_float(), right.as_float()) {
+                    (Some(left), Some(right)) => Ok(Value::Bool(compare(*op, left, right))),
+                    _ => Err(RuntimeError {
+                        span: *span,
+                        kind: RuntimeErrorKind::TypeMismatch,
+                    }),
+                },
+            },
+            Ast::Neg { span, expr } => match eval(expr, env, constants, steps, trace, mode)? {
+                // Negation is `0 - val`, which only fits when `val` is 0 unless it wraps.
+                Value::Int(val) => apply_int(Op::Sub, 0, val, mode)
+                    .map(Value::Int)
+                    .map_err(|kind| RuntimeError { span: *span, kind }),
+                Value::Float(val) => Ok(Value::Float(-val)),
+                _ => Err(RuntimeError {
+                    span: *span,
+                    kind: RuntimeErrorKind::NotANumber,
+                }),
+            },
+            Ast::Not { span, expr } => match eval(expr, env, constants, steps, trace, mode)? {
+                Value::Int(val) => Ok(Value::Int(mode.mask(!val))),
+                _ => Err(RuntimeError {
+                    span: *span,
+                    kind: RuntimeErrorKind::NotAnInteger,
+                }),
+            },
+            Ast::Group { expr, .. } => eval(expr, env, constants, steps, trace, mode),
+            Ast::Let {
+                name, value, body, ..
+            } => {
+                let value = eval(value, env, constants, steps, trace, mode)?;
+                let outer = env.insert(name.clone(), value);
+                let result = eval(body, env, constants, steps, trace, mode);
+
+                match outer {
+                    Some(outer) => env.insert(name.clone(), outer),
+                    None => env.remove(name),
+                };
+
+                result
+            }
+            // Only the branch taken is evaluated.
+            Ast::If {
+                cond, then, else_, ..
+            } => match eval(cond, env, constants, steps, trace, mode)? {
+                Value::Bool(true) => eval(then, env, constants, steps, trace, mode),
+                Value::Bool(false) => eval(else_, env, constants, steps, trace, mode),
+                _ => Err(RuntimeError {
+                    span: cond.span(),
+                    kind: RuntimeErrorKind::NotABoolean,
+                }),
+            },
+            Ast::Call {
+                span, name, args, ..
+            } => {
+                let args = args
+                    .iter()
+                    .map(|arg| eval(arg, env, constants, steps, trace, mode))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                call(name, &args, mode).map_err(|kind| RuntimeError { span: *span, kind })
+            }
+            // Analysis has already rejected unknown variables.
+            Ast::Var { name, .. } => Ok(env[name].clone()),
+            Ast::Error { .. } => unreachable!("analysis rejects programs with syntax errors"),
+        }
+    })
+}
+
+/// Calls the built-in function `name`. Analysis has already checked the name and the
+/// number of arguments, so only their types can be wrong. Integers are unsigned here as
+/// they are everywhere else, so `abs` returns them unchanged and `gcd` and `lcm` have no
+/// negative arguments to take the absolute value of.
+pub fn call(name: &str, args: &[Value], mode: IntMode) -> Result<Value, RuntimeErrorKind> {
+    match (name, args) {
+        ("int", [Value::Int(val)]) => return Ok(Value::Int(*val)),
+        ("float", [Value::Int(val)]) => return Ok(Value::Float(*val as f64)),
+        ("gcd", [Value::Int(a), Value::Int(b)]) => return Ok(Value::Int(gcd(*a, *b))),
+        // The product of the arguments could overflow where their multiple doesn't.
+        ("lcm", [Value::Int(0), Value::Int(_)]) | ("lcm", [Value::Int(_), Value::Int(0)]) => {
+            return Ok(Value::Int(0))
+        }
+        ("lcm", [Value::Int(a), Value::Int(b)]) => {
+            return apply_int(Op::Mul, a / gcd(*a, *b), *b, mode).map(Value::Int)
+        }
+        ("sqrt", [Value::Int(val)]) => return Ok(Value::Int(isqrt(*val))),
+        ("abs", [Value::Int(val)]) => return Ok(Value::Int(*val)),
+        ("min", [Value::Int(a), Value::Int(b)]) => return Ok(Value::Int(*a.min(b))),
+        ("max", [Value::Int(a), Value::Int(b)]) => return Ok(Value::Int(*a.max(b))),
+        _ => {}
+    }
+
+    // Otherwise at least one argument is a float and the rest are promoted.
+    let args = args
+        .iter()
+        .map(|arg| arg.as_float())
+        .collect::<Option<Vec<_>>>()
+        .ok_or(RuntimeErrorKind::NotANumber)?;
+
+    match (name, &args[..]) {
+        ("int", [val]) => to_int(*val, mode).map(Value::Int),
+        ("float", [val]) => Ok(Value::Float(*val)),
+        ("sqrt", [val]) => Ok(Value::Float(val.sqrt())),
+        ("abs", [val]) => Ok(Value::Float(val.abs())),
+        ("min", [a, b]) => Ok(Value::Float(a.min(*b))),
+        ("max", [a, b]) => Ok(Value::Float(a.max(*b))),
+        _ => Err(RuntimeErrorKind::BadCall),
+    }
+}
+
+/// Truncates `val` towards zero, failing unless the result fits in an integer.
+fn to_int(val: f64, mode: IntMode) -> Result<u64, RuntimeErrorKind> {
+    let val = val.trunc();
+
+    // 2^width is exact as a float where the largest integer might not be. NaN fails both.
+    if val >= 0.0 && val < 2f64.powi(mode.width as i32) {
+        Ok(val as u64)
+    } else {
+        Err(RuntimeErrorKind::OutOfRange)
+    }
+}
+
+/// The greatest common divisor of `a` and `b`, 0 only if both are.
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let rem = a % b;
+
+        a = b;
+        b = rem;
+    }
+
+    a
+}
+
+/// The square root of `val`, rounded down. The float estimate can be off by one for
+/// values beyond 2^52, so it is corrected in both directions.
+fn isqrt(val: u64) -> u64 {
+    let mut root = (val as f64).sqrt() as u64;
+
+    while root.checked_mul(root).map_or(true, |square| square > val) {
+        root -= 1;
+    }
+
+    while (root + 1)
+        .checked_mul(root + 1)
+        .map_or(false, |square| square <= val)
+    {
+        root += 1;
+    }
+
+    root
+}
+
+pub fn apply_int(op: Op, left: u64, right: u64, mode: IntMode) -> Result<u64, RuntimeErrorKind> {
+    // Wrapping at 64 bits and then truncating gives the same low bits as wrapping at the
+    // width itself.
+    if mode.overflow == Overflow::Wrap {
+        match op {
+            Op::Add => return Ok(mode.mask(left.wrapping_add(right))),
+            Op::Sub => return Ok(mode.mask(left.wrapping_sub(right))),
+            Op::Mul => return Ok(mode.mask(left.wrapping_mul(right))),
+            Op::Pow => return Ok(mode.mask(wrapping_pow(left, right))),
+            _ => {}
+        }
+    }
+
+    let result = match op {
+        Op::Add => left
+            .checked_add(right)
+            .ok_or(RuntimeErrorKind::Overflow(op)),
+        Op::Sub => left.checked_sub(right).ok_or(RuntimeErrorKind::Underflow),
+        Op::Mul => left
+            .checked_mul(right)
+            .ok_or(RuntimeErrorKind::Overflow(op)),
+        Op::Div => left
+            .checked_div(right)
+            .ok_or(RuntimeErrorKind::DivisionByZero),
+        Op::Mod => left
+            .checked_rem(right)
+            .ok_or(RuntimeErrorKind::DivisionByZero),
+        Op::Pow => checked_pow(left, right).ok_or(RuntimeErrorKind::Overflow(op)),
+        Op::BitAnd => Ok(left & right),
+        Op::BitOr => Ok(left | right),
+        Op::BitXor => Ok(left ^ right),
+    }?;
+
+    if result > mode.max() {
+        Err(RuntimeErrorKind::Overflow(op))
+    } else {
+        Ok(result)
+    }
+}
+
+fn apply_float(op: Op, left: f64, right: f64) -> Result<f64, RuntimeErrorKind> {
+    match op {
+        Op::Add => Ok(left + right),
+        Op::Sub => Ok(left - right),
+        Op::Mul => Ok(left * right),
+        Op::Div => Ok(left / right),
+        Op::Mod => Ok(left % right),
+        Op::Pow => Ok(left.powf(right)),
+        Op::BitAnd | Op::BitOr | Op::BitXor => Err(RuntimeErrorKind::NotAnInteger),
+    }
+}
+
+fn compare<T: PartialOrd>(op: CmpOp, left: T, right: T) -> bool {
+    match op {
+        CmpOp::Lt => left < right,
+        CmpOp::Gt => left > right,
+        CmpOp::Le => left <= right,
+        CmpOp::Ge => left >= right,
+        CmpOp::Eq => left == right,
+        CmpOp::Ne => left != right,
+    }
+}
+
+fn wrapping_pow(mut base: u64, mut exponent: u64) -> u64 {
+    let mut result = 1u64;
+
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
+    }
+
+    result
+}
+
+fn checked_pow(base: u64, exponent: u64) -> Option<u64> {
+    match u32::try_from(exponent) {
+        Ok(exponent) => base.checked_pow(exponent),
+        // Exponents this large only fit for bases that never grow.
+        Err(_) if base <= 1 => Some(base),
+        Err(_) => None,
+    }
+}
+
+impl From<RuntimeError> for Diagnostic {
+    fn from(error: RuntimeError) -> Diagnostic {
+        Diagnostic::new(Severity::Error, None, error.kind.to_string()).label(
+            Severity::Error,
+            error.span,
+            None::<String>,
+        )
+    }
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Overflow(Op::Add) => write!(f, "Integer overflow in addition"),
+            Self::Overflow(Op::Mul) => write!(f, "Integer overflow in multiplication"),
+            Self::Overflow(Op::Pow) => write!(f, "Integer overflow in exponentiation"),
+            Self::Overflow(_) => write!(f, "Integer overflow"),
+            Self::Underflow => write!(f, "Integer underflow"),
+            Self::DivisionByZero => write!(f, "Cannot divide by 0"),
+            Self::TypeMismatch => write!(f, "Cannot compare values of different types"),
+            Self::NotANumber => write!(f, "Arithmetic only applies to numbers"),
+            Self::NotAnInteger => write!(f, "Bitwise operators only apply to integers"),
+            Self::NotABoolean => write!(f, "Conditions must be booleans"),
+            Self::StepLimitExceeded => write!(f, "Step limit exceeded"),
+            Self::OutOfRange => write!(f, "Float is out of the integer range"),
+            Self::BadCall => write!(f, "Invalid call to a built-in function"),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Int(val) => write!(f, "{}", val),
+            Self::Float(val) => write!(f, "{:?}", val),
+            Self::Bool(val) => write!(f, "{}", val),
+            Self::Str(val) => write!(f, "{:?}", val),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_test;
+
+    fn eval_str(source: &str, mode: IntMode) -> Result<Value, RuntimeErrorKind> {
+        let program = parse_test(source).expect("syntax error");
+        let constants = Constants::find(&program, mode);
+
+        run(&program[0], &constants, mode).map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn chars_are_code_points() {
+        let mode = IntMode::default();
+
+        assert_eq!(eval_str("'a'", mode), Ok(Value::Int(97)));
+        assert_eq!(eval_str("'a' + 1", mode), Ok(Value::Int(98)));
+        assert_eq!(eval_str("'é'", mode), Ok(Value::Int(233)));
+    }
+
+    #[test]
+    fn negation_underflows() {
+        let mode = IntMode::default();
+        let underflow = Err(RuntimeErrorKind::Underflow);
+
+        assert_eq!(eval_str("-5 + 3", mode), underflow);
+        assert_eq!(eval_str("let x = 5 in -x", mode), underflow);
+        assert_eq!(eval_str("-0", mode), Ok(Value::Int(0)));
+        assert_eq!(eval_str("-2.5", mode), Ok(Value::Float(-2.5)));
+        assert_eq!(eval_str("-5 + 3", WRAP_64), Ok(Value::Int(u64::MAX - 1)));
+        assert_eq!(eval_str("-1", WRAP_8), Ok(Value::Int(255)));
+    }
+
+    #[test]
+    fn builtins_are_unsigned() {
+        let mode = IntMode::default();
+
+        assert_eq!(eval_str("abs(5)", mode), Ok(Value::Int(5)));
+        assert_eq!(eval_str("abs(-5)", mode), Err(RuntimeErrorKind::Underflow));
+        assert_eq!(
+            eval_str("min(-5, 3)", mode),
+            Err(RuntimeErrorKind::Underflow)
+        );
+        assert_eq!(
+            eval_str("max(-5, 3)", WRAP_64),
+            Ok(Value::Int(5u64.wrapping_neg()))
+        );
+        assert_eq!(eval_str("abs(-2.5)", mode), Ok(Value::Float(2.5)));
+    }
+
+    #[test]
+    fn bad_calls() {
+        let bad_call = Err(RuntimeErrorKind::BadCall);
+
+        let mode = IntMode::default();
+
+        assert_eq!(call("nope", &[Value::Int(1)], mode), bad_call);
+        assert_eq!(
+            call("abs", &[Value::Int(1), Value::Float(2.0)], mode),
+            bad_call
+        );
+        assert_eq!(call("min", &[Value::Float(1.0)], mode), bad_call);
+        assert_eq!(
+            call("gcd", &[Value::Float(4.0), Value::Int(2)], mode),
+            bad_call
+        );
+        assert_eq!(
+            call("sqrt", &[Value::Bool(true)], mode),
+            Err(RuntimeErrorKind::NotANumber)
+        );
+    }
+
+    #[test]
+    fn conversions() {
+        let mode = IntMode::default();
+        let out_of_range = Err(RuntimeErrorKind::OutOfRange);
+
+        assert_eq!(eval_str("int(3.9)", mode), Ok(Value::Int(3)));
+        assert_eq!(eval_str("int(-0.5)", mode), Ok(Value::Int(0)));
+        assert_eq!(eval_str("int(7)", mode), Ok(Value::Int(7)));
+        assert_eq!(eval_str("float(5)", mode), Ok(Value::Float(5.0)));
+        assert_eq!(eval_str("float(2.5)", mode), Ok(Value::Float(2.5)));
+        assert_eq!(eval_str("int(-1.5)", mode), out_of_range);
+        assert_eq!(eval_str("int(1.0e20)", mode), out_of_range);
+        assert_eq!(eval_str("int(0.0 / 0.0)", mode), out_of_range);
+        assert_eq!(eval_str("int(255.5)", TRAP_8), Ok(Value::Int(255)));
+        assert_eq!(eval_str("int(256.0)", TRAP_8), out_of_range);
+    }
+
+    #[test]
+    fn approx_eq() {
+        let close = |a: f64, b: f64| Value::Float(a).approx_eq(&Value::Float(b), 1e-9);
+
+        assert!(close(0.1 + 0.2, 0.3));
+        assert!(close(1e20, 1e20 + 1e5));
+        assert!(!close(1.0, 1.001));
+        assert!(close(f64::NAN, f64::NAN));
+        assert!(!close(f64::NAN, 1.0));
+        assert!(close(f64::INFINITY, f64::INFINITY));
+        assert!(!close(f64::INFINITY, f64::NEG_INFINITY));
+        assert!(!Value::Int(1).approx_eq(&Value::Float(1.0), 1e-9));
+        assert!(Value::Int(7).approx_eq(&Value::Int(7), 1e-9));
+
+        let mode = IntMode::default();
+
+        assert!(eval_str("sqrt(2.0) ** 2", mode)
+            .unwrap()
+            .approx_eq(&Value::Float(2.0), 1e-9));
+        assert!(eval_str("0.0 / 0.0", mode)
+            .unwrap()
+            .approx_eq(&Value::Float(f64::NAN), 1e-9));
+    }
+
+    #[test]
+    fn gcd_and_lcm() {
+        let mode = IntMode::default();
+
+        assert_eq!(eval_str("gcd(12, 18)", mode), Ok(Value::Int(6)));
+        assert_eq!(eval_str("gcd(0, 7)", mode), Ok(Value::Int(7)));
+        assert_eq!(eval_str("gcd(7, 0)", mode), Ok(Value::Int(7)));
+        assert_eq!(eval_str("gcd(0, 0)", mode), Ok(Value::Int(0)));
+        assert_eq!(eval_str("lcm(4, 6)", mode), Ok(Value::Int(12)));
+        assert_eq!(eval_str("lcm(0, 7)", mode), Ok(Value::Int(0)));
+        assert_eq!(eval_str("lcm(0, 0)", mode), Ok(Value::Int(0)));
+        // The product overflows, the multiple itself doesn't.
+        assert_eq!(
+            eval_str("lcm(2 ** 40, 2 ** 40)", mode),
+            Ok(Value::Int(1 << 40))
+        );
+        assert_eq!(
+            eval_str("lcm(2 ** 32 + 1, 2 ** 32)", mode),
+            overflow(Op::Mul)
+        );
+        assert_eq!(eval_str("lcm(16, 17)", TRAP_8), overflow(Op::Mul));
+        assert_eq!(eval_str("lcm(16, 17)", WRAP_8), Ok(Value::Int(16)));
+    }
+
+    const TRAP_8: IntMode = IntMode {
+        width: 8,
+        overflow: Overflow::Trap,
+    };
+    const WRAP_8: IntMode = IntMode {
+        width: 8,
+        overflow: Overflow::Wrap,
+    };
+    const WRAP_64: IntMode = IntMode {
+        width: 64,
+        overflow: Overflow::Wrap,
+    };
+
+    fn overflow<T>(op: Op) -> Result<T, RuntimeErrorKind> {
+        Err(RuntimeErrorKind::Overflow(op))
+    }
+
+    #[test]
+    fn pow_is_right_associative() {
+        let mode = IntMode::default();
+
+        assert_eq!(eval_str("2 ** 3 ** 2", mode), Ok(Value::Int(512)));
+        assert_eq!(eval_str("(2 ** 3) ** 2", mode), Ok(Value::Int(64)));
+        assert_eq!(eval_str("2 * 3 ** 2", mode), Ok(Value::Int(18)));
+        assert_eq!(eval_str("0 ** 0", mode), Ok(Value::Int(1)));
+    }
+
+    #[test]
+    fn overflow_traps() {
+        let mode = IntMode::default();
+
+        assert_eq!(apply_int(Op::Add, u64::MAX, 1, mode), overflow(Op::Add));
+        assert_eq!(
+            apply_int(Op::Mul, 1 << 32, 1 << 32, mode),
+            overflow(Op::Mul)
+        );
+        assert_eq!(apply_int(Op::Pow, 2, 64, mode), overflow(Op::Pow));
+        assert_eq!(apply_int(Op::Pow, 2, 63, mode), Ok(1 << 63));
+        assert_eq!(apply_int(Op::Pow, 1, u64::MAX, mode), Ok(1));
+
+        assert_eq!(apply_int(Op::Add, 255, 1, TRAP_8), overflow(Op::Add));
+        assert_eq!(apply_int(Op::Mul, 16, 16, TRAP_8), overflow(Op::Mul));
+        assert_eq!(apply_int(Op::Pow, 2, 8, TRAP_8), overflow(Op::Pow));
+        assert_eq!(apply_int(Op::Pow, 2, 7, TRAP_8), Ok(128));
+
+        assert_eq!(eval_str("2 ** 64", mode), overflow(Op::Pow));
+    }
+
+    #[test]
+    fn overflow_wraps() {
+        assert_eq!(apply_int(Op::Add, u64::MAX, 1, WRAP_64), Ok(0));
+        assert_eq!(apply_int(Op::Mul, 1 << 32, 1 << 32, WRAP_64), Ok(0));
+        assert_eq!(apply_int(Op::Pow, 2, 64, WRAP_64), Ok(0));
+        assert_eq!(
+            apply_int(Op::Pow, 3, 41, WRAP_64),
+            Ok(3u64.wrapping_pow(41))
+        );
+
+        assert_eq!(apply_int(Op::Add, 255, 1, WRAP_8), Ok(0));
+        assert_eq!(apply_int(Op::Mul, 16, 17, WRAP_8), Ok(16));
+        assert_eq!(apply_int(Op::Pow, 3, 6, WRAP_8), Ok(729 % 256));
+
+        assert_eq!(eval_str("2 ** 64", WRAP_64), Ok(Value::Int(0)));
+        assert_eq!(eval_str("200 + 100", WRAP_8), Ok(Value::Int(44)));
+    }
+
+    #[test]
+    fn top_level_results_have_spans() {
+        let program = parse_test("1 + 2;\n  3 * 4").expect("syntax error");
+        let mode = IntMode::default();
+        let constants = Constants::find(&program, mode);
+        let results = run_top_level(&program, &constants, mode).unwrap();
+        let results = results
+            .iter()
+            .map(|(span, value)| (span.start.line, span.start.offset, span.end.offset, value))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            results,
+            [(0, 0, 5, &Value::Int(3)), (1, 9, 14, &Value::Int(12))]
+        );
+    }
+
+    #[test]
+    fn top_level_stops_at_first_error() {
+        let program = parse_test("1; 0 - 1; 2").expect("syntax error");
+        let mode = IntMode::default();
+        let constants = Constants::find(&program, mode);
+
+        assert_eq!(
+            run_top_level(&program, &constants, mode).map_err(|e| e.kind),
+            Err(RuntimeErrorKind::Underflow)
+        );
+    }
+
+    #[test]
+    fn step_limit_counts_constant_nodes() {
+        let program = parse_test("1 + 2 + 3 + 4").expect("syntax error");
+        let mode = IntMode::default();
+
+        // Three operations and four literals.
+        assert_eq!(run_with_limit(&program[0], mode, 7), Ok(Value::Int(10)));
+        assert_eq!(
+            run_with_limit(&program[0], mode, 6).map_err(|e| e.kind),
+            Err(RuntimeErrorKind::StepLimitExceeded)
+        );
     }
 }
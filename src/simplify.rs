@@ -0,0 +1,271 @@
+//! Rewrites operations with an identity as one of their operands, like `x + 0`, into a
+//! simpler equivalent. Only integer literals are recognized as identities, a float one would
+//! turn an integer operation into a float one.
+
+use crate::analysis::{self, Type};
+use crate::ast::*;
+use crate::codegen::fold::fold;
+use crate::constants::Constants;
+use crate::format;
+use crate::run::IntMode;
+
+enum Rewrite {
+    Left,
+    Right,
+    Zero,
+    Keep,
+}
+
+/// Copies `ast`, simplifying its operations. These always apply:
+///
+/// - `x + 0` and `0 + x` to `x`, for an integer `x`
+/// - `x - 0`, `x * 1`, `1 * x`, `x / 1` and `x ** 1` to `x`, for any number `x`
+///
+/// These only apply with `unsafe_math`. The first ones drop `x`, so a division by zero or an
+/// overflow within it no longer traps, the last one turns `-0.0` into `0.0`:
+///
+/// - `x * 0`, `0 * x` and `x - x` to `0`, for an integer `x`
+/// - `x + 0` and `0 + x` to `x`, for a float `x`
+pub fn simplify(ast: &Ast, unsafe_math: bool) -> Ast {
+    simplify_in(ast, &mut Vec::new(), unsafe_math)
+}
+
+/// Folds the constants of `program` and simplifies it, then folds the constants simplifying
+/// exposed, so `x * 0 + 1` becomes `1` with `unsafe_math`.
+pub fn normalize(program: &[Ast], mode: IntMode, unsafe_math: bool) -> Vec<Ast> {
+    let constants = Constants::find(program, mode);
+    let program = program
+        .iter()
+        .map(|ast| simplify(&fold(ast, &constants), unsafe_math))
+        .collect::<Vec<_>>();
+    let constants = Constants::find(&program, mode);
+
+    program.iter().map(|ast| fold(ast, &constants)).collect()
+}
+
+/// `scope` holds the types of the variables bound around `ast`, innermost last.
+fn simplify_in<'a>(ast: &'a Ast, scope: &mut Vec<(&'a str, Type)>, unsafe_math: bool) -> Ast {
+    crate::grow_stack(|| match ast {
+        Ast::Int { .. }
+        | Ast::Float { .. }
+        | Ast::Str { .. }
+        | Ast::Var { .. }
+        | Ast::Error { .. } => ast.clone(),
+        Ast::Op {
+            span,
+            op,
+            op_span,
+            left,
+            right,
+        } => {
+            // Simplifying doesn't change the type of an operand, so the original is inferred.
+            let types = (
+                analysis::infer(left, scope).ok(),
+                analysis::infer(right, scope).ok(),
+            );
+            let left = simplify_in(left, scope, unsafe_math);
+            let right = simplify_in(right, scope, unsafe_math);
+
+            match rewrite(*op, &left, &right, types, unsafe_math) {
+                Rewrite::Left => left,
+                Rewrite::Right => right,
+                Rewrite::Zero => Ast::Int {
+                    span: *span,
+                    val: 0,
+                },
+                Rewrite::Keep => Ast::Op {
+                    span: *span,
+                    op: *op,
+                    op_span: *op_span,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+            }
+        }
+        Ast::Cmp {
+            span,
+            op,
+            left,
+            right,
+        } => Ast::Cmp {
+            span: *span,
+            op: *op,
+            left: Box::new(simplify_in(left, scope, unsafe_math)),
+            right: Box::new(simplify_in(right, scope, unsafe_math)),
+        },
+        Ast::Neg { span, expr } => Ast::Neg {
+            span: *span,
+            expr: Box::new(simplify_in(expr, scope, unsafe_math)),
+        },
+        Ast::Not { span, expr } => Ast::Not {
+            span: *span,
+            expr: Box::new(simplify_in(expr, scope, unsafe_math)),
+        },
+        Ast::Group { span, expr } => Ast::Group {
+            span: *span,
+            expr: Box::new(simplify_in(expr, scope, unsafe_math)),
+        },
+        Ast::Let {
+            span,
+            name,
+            value,
+            body,
+        } => {
+            // A program with a type error is rejected by analysis either way, the body of a
+            // binding without a type is left as is.
+            let body = match analysis::infer(value, scope) {
+                Ok(ty) => {
+                    scope.push((name.as_str(), ty));
+
+                    let body = simplify_in(body, scope, unsafe_math);
+
+                    scope.pop();
+                    body
+                }
+                Err(_) => (**body).clone(),
+            };
+
+            Ast::Let {
+                span: *span,
+                name: name.clone(),
+                value: Box::new(simplify_in(value, scope, unsafe_math)),
+                body: Box::new(body),
+            }
+        }
+        Ast::If {
+            span,
+            cond,
+            then,
+            else_,
+        } => Ast::If {
+            span: *span,
+            cond: Box::new(simplify_in(cond, scope, unsafe_math)),
+            then: Box::new(simplify_in(then, scope, unsafe_math)),
+            else_: Box::new(simplify_in(else_, scope, unsafe_math)),
+        },
+        Ast::Call {
+            span,
+            name,
+            name_span,
+            args,
+        } => Ast::Call {
+            span: *span,
+            name: name.clone(),
+            name_span: *name_span,
+            args: args
+                .iter()
+                .map(|arg| simplify_in(arg, scope, unsafe_math))
+                .collect(),
+        },
+    })
+}
+
+/// Picks the rewrite of `left op right`, given the types of both operands.
+fn rewrite(
+    op: Op,
+    left: &Ast,
+    right: &Ast,
+    types: (Option<Type>, Option<Type>),
+    unsafe_math: bool,
+) -> Rewrite {
+    let number = |ty: Option<Type>| ty == Some(Type::Int) || ty == Some(Type::Float);
+    let plus_zero = |ty: Option<Type>| ty == Some(Type::Int) || unsafe_math && number(ty);
+    let ints = types == (Some(Type::Int), Some(Type::Int));
+
+    match (op, literal(left), literal(right)) {
+        (Op::Add, _, Some(0)) if plus_zero(types.0) => Rewrite::Left,
+        (Op::Add, Some(0), _) if plus_zero(types.1) => Rewrite::Right,
+        (Op::Sub, _, Some(0))
+        | (Op::Mul, _, Some(1))
+        | (Op::Div, _, Some(1))
+        | (Op::Pow, _, Some(1))
+            if number(types.0) =>
+        {
+            Rewrite::Left
+        }
+        (Op::Mul, Some(1), _) if number(types.1) => Rewrite::Right,
+        (Op::Mul, Some(0), _) | (Op::Mul, _, Some(0)) if unsafe_math && ints => Rewrite::Zero,
+        (Op::Sub, _, _) if unsafe_math && ints && same(left, right) => Rewrite::Zero,
+        _ => Rewrite::Keep,
+    }
+}
+
+/// The value of `ast` if it is an integer literal, possibly in parentheses.
+fn literal(ast: &Ast) -> Option<u64> {
+    match ast {
+        Ast::Int { val, .. } => Some(*val),
+        Ast::Group { expr, .. } => literal(expr),
+        _ => None,
+    }
+}
+
+/// Whether `left` and `right` are the same expression, regardless of where they are and the
+/// parentheses around them. The formatter prints exactly that.
+fn same(left: &Ast, right: &Ast) -> bool {
+    format::format(std::slice::from_ref(left)) == format::format(std::slice::from_ref(right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_test;
+
+    fn simplify_str(source: &str, unsafe_math: bool) -> String {
+        let program = parse_test(source).expect("syntax error");
+        let program = program
+            .iter()
+            .map(|ast| simplify(ast, unsafe_math))
+            .collect::<Vec<_>>();
+
+        format::format(&program).trim_end().to_string()
+    }
+
+    #[test]
+    fn identities() {
+        for source in &[
+            "x + 0", "0 + x", "x - 0", "x * 1", "1 * x", "x / 1", "x ** 1",
+        ] {
+            assert_eq!(simplify_str(source, false), "x", "{}", source);
+        }
+
+        assert_eq!(simplify_str("((x + 0) * 1) - (0)", false), "x");
+        assert_eq!(
+            simplify_str("let y = 2.5 in y * 1", false),
+            "let y = 2.5 in y"
+        );
+        assert_eq!(simplify_str("x * 1.0", false), "x * 1.0");
+        assert_eq!(simplify_str("x % 1 + (1 - x)", false), "x % 1 + (1 - x)");
+    }
+
+    #[test]
+    fn unsafe_identities() {
+        for source in &["x * 0", "0 * x", "x / y - x / y", "1 / 0 * 0"] {
+            assert_eq!(simplify_str(source, false), *source);
+            assert_eq!(simplify_str(source, true), "0", "{}", source);
+        }
+
+        let float = "let y = 2.5 in y + 0";
+
+        assert_eq!(simplify_str(float, false), float);
+        assert_eq!(simplify_str(float, true), "let y = 2.5 in y");
+        assert_eq!(
+            simplify_str("let y = 2.5 in y * 0", true),
+            "let y = 2.5 in y * 0"
+        );
+        assert_eq!(simplify_str("x - y", true), "x - y");
+    }
+
+    #[test]
+    fn normalizing_folds_what_simplifying_exposes() {
+        let normalize_str = |source: &str, unsafe_math: bool| {
+            let program = parse_test(source).expect("syntax error");
+
+            format::format(&normalize(&program, IntMode::default(), unsafe_math))
+        };
+        let source = "(1 + 2) * (3 + 4) - 0 * x";
+
+        assert_eq!(normalize_str(source, false), "21 - 0 * x\n");
+        assert_eq!(normalize_str(source, true), "21\n");
+        assert_eq!(normalize_str("(x * 0 + 2) * 3; x + 0", true), "6;\nx\n");
+    }
+}